@@ -0,0 +1,200 @@
+use std::{cmp::Reverse, collections::HashSet};
+
+use crate::{clock::Deadline, def::*, dijkstra::Dijkstra, grid::Grid};
+
+/// 水源から始めて家を1つずつ繋いでいく途中の木
+#[derive(Clone)]
+struct Node {
+    in_tree: Vec2d<bool>,
+    tree: Vec<Pos>,
+    /// 木に含まれるマスの重みの和
+    cost: i64,
+    /// まだ繋いでいない家の添字と、今の木からその家までの最短路（家から木の手前まで）
+    remaining: Vec<(usize, Vec<Pos>)>,
+    /// cost + 残りの家を繋ぐコストの下界
+    score: i64,
+    /// 毎回最も安く繋がる家を繋いできた木か（Primの順番で繋いだ木と同じになる）
+    is_greedy: bool,
+}
+
+/// 家を1つずつ木に繋ぎ、途中の木を評価値の小さい順にwidth個残すビームサーチで経路を作る
+///
+/// 残りの家はそれぞれ今の木から最短路以上のコストで繋がるので、その最大値を下界として評価値に加える
+/// 最も安く繋がる家を繋いでいく木は必ず残すので、Primの順番で繋いだ木より高くはならない
+pub struct BeamSearch<'a> {
+    grid: &'a Grid,
+    dijkstra: Dijkstra,
+}
+
+impl<'a> BeamSearch<'a> {
+    pub fn new(grid: &'a Grid) -> BeamSearch<'a> {
+        BeamSearch {
+            grid,
+            dijkstra: Dijkstra::new(grid.n, grid.m, Neighborhood::Four),
+        }
+    }
+
+    /// 全ての家を水源に繋ぐ木に含まれるマスを返す
+    ///
+    /// 期限を過ぎたら、最も評価値の小さい木に、最も安く繋がる家を順に繋いで終える
+    pub fn solve(&mut self, width: usize, deadline: &Deadline) -> Vec<Pos> {
+        let mut root = Node {
+            in_tree: Vec2d::new(self.grid.n, self.grid.m, false),
+            tree: vec![],
+            cost: 0,
+            remaining: (0..self.grid.house.len()).map(|i| (i, vec![])).collect(),
+            score: 0,
+            is_greedy: true,
+        };
+        for s in self.grid.source.iter() {
+            self.add(&mut root, s);
+        }
+        self.evaluate(&mut root);
+
+        let mut beam = vec![root];
+        let mut best: Option<Node> = None;
+        while !beam.is_empty() {
+            let is_timed_out = deadline.is_passed();
+            if is_timed_out {
+                beam.truncate(1);
+            }
+            let mut children = vec![];
+            for node in beam.iter() {
+                let greedy = node
+                    .remaining
+                    .iter()
+                    .min_by_key(|(j, path)| self.greedy_key(*j, path))
+                    .map(|(j, _)| *j);
+                for (i, path) in node.remaining.iter() {
+                    // 期限を過ぎたら、最も安く繋がる家だけを繋ぐ
+                    if is_timed_out && Some(*i) != greedy {
+                        continue;
+                    }
+                    let mut child = node.clone();
+                    child.is_greedy = node.is_greedy && Some(*i) == greedy;
+                    child.remaining.retain(|(j, _)| j != i);
+                    for p in path.iter() {
+                        self.add(&mut child, p);
+                    }
+                    // 途中で通った家は繋がっている
+                    child
+                        .remaining
+                        .retain(|(j, _)| !child.in_tree.get(&self.grid.house[*j]));
+                    if child.remaining.is_empty() {
+                        if best.as_ref().is_none_or(|b| child.cost < b.cost) {
+                            best = Some(child);
+                        }
+                        continue;
+                    }
+                    if child.is_greedy {
+                        children.insert(0, child);
+                    } else {
+                        children.push(child);
+                    }
+                }
+            }
+
+            // 同じ家が残っていてコストも同じ木は、同じ木とみなす
+            let mut seen = HashSet::new();
+            children.retain(|c| {
+                let houses: Vec<usize> = c.remaining.iter().map(|(j, _)| *j).collect();
+                seen.insert((c.cost, houses))
+            });
+            for child in children.iter_mut() {
+                self.evaluate(child);
+            }
+            children.sort_by_key(|c| (c.score, c.cost));
+            if let Some(i) = children.iter().position(|c| c.is_greedy) {
+                if i >= width {
+                    children.swap(width - 1, i);
+                }
+            }
+            children.truncate(width);
+            beam = children;
+        }
+
+        best.unwrap().tree
+    }
+
+    /// 最も安く繋がる家を選ぶ時の順序（同じコストなら、ダイクストラ法で先に確定する家を選ぶ）
+    fn greedy_key(&self, j: usize, path: &[Pos]) -> (i64, Reverse<Pos>) {
+        let cost = path.iter().map(|p| self.grid.cell_cost(p)).sum();
+        (cost, Reverse(self.grid.house[j]))
+    }
+
+    fn add(&self, node: &mut Node, p: &Pos) {
+        if node.in_tree.get(p) {
+            return;
+        }
+        node.in_tree.set(p, true);
+        node.tree.push(*p);
+        node.cost += self.grid.cell_cost(p);
+    }
+
+    /// 木から残りの家までの最短路を求め、評価値を更新する
+    fn evaluate(&mut self, node: &mut Node) {
+        let grid = self.grid;
+        let is_remaining = |p: &Pos| node.remaining.iter().any(|(j, _)| grid.house[*j] == *p);
+        // 残りの家が全て確定したら打ち切る
+        let mut reached = 0;
+        self.dijkstra.run(
+            &node.tree,
+            INF,
+            |p| grid.cell_cost(p),
+            |p| {
+                if is_remaining(p) {
+                    reached += 1;
+                }
+                reached == node.remaining.len()
+            },
+            |_| 0,
+        );
+
+        let mut lower_bound = 0;
+        for (j, path) in node.remaining.iter_mut() {
+            let h = grid.house[*j];
+            lower_bound = lower_bound.max(self.dijkstra.dist(&h));
+            *path = self.dijkstra.path_to(&h);
+            // 始点は木に含まれている
+            path.remove(0);
+        }
+        node.score = node.cost + lower_bound;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::Clock,
+        steiner::{
+            tests::{connects_all_houses, random_grid},
+            HouseOrder, SteinerSolver,
+        },
+    };
+
+    #[test]
+    fn beam_search_is_not_worse_than_prim() {
+        let clock = Clock::wall();
+        let deadline = Deadline::new(&clock, f64::INFINITY);
+        let tree_cost =
+            |grid: &Grid, tree: &[Pos]| -> i64 { tree.iter().map(|p| grid.cell_cost(p)).sum() };
+        for w in 1..=3 {
+            for seed in 0..30 {
+                let grid = random_grid(seed, 25, 30, w, 6);
+                let tree = BeamSearch::new(&grid).solve(4, &deadline);
+                let solver = SteinerSolver::new(&grid);
+                assert!(connects_all_houses(&solver, &tree));
+                let prim = solver.build(HouseOrder::Prim);
+                assert!(
+                    tree_cost(&grid, &tree) <= tree_cost(&grid, &prim),
+                    "w = {}, seed = {}: {} > {}",
+                    w,
+                    seed,
+                    tree_cost(&grid, &tree),
+                    tree_cost(&grid, &prim)
+                );
+            }
+        }
+    }
+}
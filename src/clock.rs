@@ -0,0 +1,92 @@
+use std::{cell::RefCell, collections::VecDeque};
+
+use crate::util::time;
+
+/// solverが時間で処理を打ち切る時に読む時計
+///
+/// 記録したやり取りを再生する時は、記録した時刻を読んだ順に返すので、
+/// 焼きなましや経路の選び直しが記録した時と同じところで打ち切られる
+pub struct Clock {
+    /// 再生する時刻（Noneなら実際の経過時間を返す）
+    replay: Option<RefCell<VecDeque<f64>>>,
+    /// 記録するために読んだ時刻（記録しない時はNone）
+    readings: Option<RefCell<Vec<f64>>>,
+}
+
+impl Clock {
+    /// プログラムの開始からの経過時間を返す時計
+    pub fn wall() -> Clock {
+        Clock {
+            replay: None,
+            readings: None,
+        }
+    }
+
+    /// readingsを順に返す時計
+    /// 使い切った後は、全ての時間の上限を過ぎたものとして扱う
+    pub fn replay(readings: Vec<f64>) -> Clock {
+        Clock {
+            replay: Some(RefCell::new(readings.into())),
+            readings: None,
+        }
+    }
+
+    /// これ以降に読んだ時刻を記録する
+    pub fn start_recording(&mut self) {
+        self.readings = Some(RefCell::new(vec![]));
+    }
+
+    /// 経過時間（秒）
+    pub fn elapsed_seconds(&self) -> f64 {
+        let t = match &self.replay {
+            Some(replay) => replay.borrow_mut().pop_front().unwrap_or(f64::INFINITY),
+            None => time::elapsed_seconds(),
+        };
+        if let Some(readings) = &self.readings {
+            readings.borrow_mut().push(t);
+        }
+        t
+    }
+
+    /// 前回取り出してから読んだ時刻を取り出す
+    pub fn take_readings(&self) -> Vec<f64> {
+        match &self.readings {
+            Some(readings) => std::mem::take(&mut *readings.borrow_mut()),
+            None => vec![],
+        }
+    }
+}
+
+/// 時計が時刻limit（秒）を過ぎたら処理を打ち切る期限
+#[derive(Clone, Copy)]
+pub struct Deadline<'a> {
+    clock: &'a Clock,
+    limit: f64,
+}
+
+impl<'a> Deadline<'a> {
+    pub fn new(clock: &'a Clock, limit: f64) -> Deadline<'a> {
+        Deadline { clock, limit }
+    }
+
+    pub fn is_passed(&self) -> bool {
+        self.clock.elapsed_seconds() >= self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_returns_recorded_readings() {
+        let mut clock = Clock::replay(vec![0.5, 1.25]);
+        clock.start_recording();
+        assert_eq!(clock.elapsed_seconds(), 0.5);
+        assert_eq!(clock.elapsed_seconds(), 1.25);
+        // 使い切った後は時間切れ
+        assert_eq!(clock.elapsed_seconds(), f64::INFINITY);
+        assert_eq!(clock.take_readings(), vec![0.5, 1.25, f64::INFINITY]);
+        assert!(clock.take_readings().is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::{estimator::*, params::Params, strike::*};
+
+/// 予測分布のもとで、マスを割るのにかかるコストの期待値を求める
+///
+/// 叩き方は`StrikePlanner`で決め、`destroy_used_path`も同じ叩き方をする
+pub struct CostModel {
+    planner: StrikePlanner,
+    /// 予測分布を丸めた値ごとの期待値
+    cache: RefCell<HashMap<(i64, i64, i64), f64>>,
+}
+
+/// 期待値を使い回す時に、log2の予測分布を丸める幅
+const CACHE_RESOLUTION: f64 = 64.;
+
+impl CostModel {
+    pub fn new(params: &Params, c: i64) -> CostModel {
+        CostModel {
+            planner: StrikePlanner::new(c, params.strike_level_ratio),
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// 既にdamageだけ与えて割れていないマスの叩き方
+    pub fn plan(&self, estimate: &Estimate, damage: i64) -> StrikePlan {
+        self.planner.plan(estimate, damage)
+    }
+
+    /// 既にdamageだけ与えて割れていないマスを割るまでの、叩く回数 * C + パワーの和の期待値
+    pub fn expected_cost(&self, estimate: &Estimate, damage: i64) -> f64 {
+        let key = (
+            (estimate.mean * CACHE_RESOLUTION).round() as i64,
+            (estimate.std * CACHE_RESOLUTION).round() as i64,
+            damage,
+        );
+        if let Some(&cost) = self.cache.borrow().get(&key) {
+            return cost;
+        }
+        let estimate = Estimate {
+            mean: key.0 as f64 / CACHE_RESOLUTION,
+            std: key.1 as f64 / CACHE_RESOLUTION,
+        };
+        let cost = self.planner.plan(&estimate, damage).expected_cost;
+        self.cache.borrow_mut().insert(key, cost);
+        cost
+    }
+}
@@ -1,11 +1,20 @@
-use std::{fs::File, io::Write};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::Write,
+    ops::{Index, IndexMut},
+};
 
 pub const INF: i64 = 100_000_000_000_000;
-pub const N: usize = 200;
 pub const S_MAX: i64 = 5000;
+pub const S_MIN: i64 = 10;
 
+#[derive(Clone)]
 pub struct Input {
+    /// 行数
     pub n: usize,
+    /// 列数（公式の入力ではnと同じ）
+    pub m: usize,
     pub w: usize,
     pub k: usize,
     pub c: i64,
@@ -13,21 +22,68 @@ pub struct Input {
     pub house: Vec<Pos>,
 }
 
+/// 掘削から分かった頑丈度の範囲（lower < 頑丈度 <= upper）
+#[derive(Clone, Copy, Debug)]
+pub struct Interval {
+    pub pos: Pos,
+    pub lower: i64,
+    /// まだ壊れていない場合はNone
+    pub upper: Option<i64>,
+}
+
 pub struct State {
     pub is_broken: Vec2d<bool>,
     pub damage: Vec2d<i64>,
     pub damage_before_break: Vec2d<i64>,
     pub total_damage: i64,
+    /// 掘削したことがある地点
+    pub probed: Vec<Pos>,
 }
 
 impl State {
-    pub fn new(n: usize) -> State {
+    pub fn new(n: usize, m: usize) -> State {
         State {
-            is_broken: Vec2d::new(n, n, false),
-            damage: Vec2d::new(n, n, 0),
-            damage_before_break: Vec2d::new(n, n, 0),
+            is_broken: Vec2d::new(n, m, false),
+            damage: Vec2d::new(n, m, 0),
+            damage_before_break: Vec2d::new(n, m, 0),
             total_damage: 0,
+            probed: vec![],
+        }
+    }
+
+    pub fn add_damage(&mut self, pos: &Pos, power: i64) {
+        if self.damage.get(pos) == 0 {
+            self.probed.push(*pos);
+        }
+        self.total_damage += power;
+        self.damage.set(pos, self.damage.get(pos) + power);
+    }
+
+    pub fn interval(&self, pos: &Pos) -> Option<Interval> {
+        if self.damage.get(pos) == 0 {
+            return None;
         }
+        let interval = if self.is_broken.get(pos) {
+            Interval {
+                pos: *pos,
+                lower: self.damage_before_break.get(pos),
+                upper: Some(self.damage.get(pos)),
+            }
+        } else {
+            Interval {
+                pos: *pos,
+                lower: self.damage.get(pos),
+                upper: None,
+            }
+        };
+        Some(interval)
+    }
+
+    pub fn intervals(&self) -> Vec<Interval> {
+        self.probed
+            .iter()
+            .filter_map(|p| self.interval(p))
+            .collect()
     }
 
     #[allow(unused)]
@@ -36,8 +92,8 @@ impl State {
             return;
         }
         let mut file = File::create(output_file).unwrap();
-        for y in 0..N {
-            for x in 0..N {
+        for y in 0..self.damage.n() {
+            for x in 0..self.damage.m() {
                 write!(
                     file,
                     "{} ",
@@ -68,15 +124,74 @@ impl Pos {
         (((to.y - self.y) * (to.y - self.y) + (to.x - self.x) * (to.x - self.x)) as f64).sqrt()
     }
 
-    pub fn is_valid(&self) -> bool {
-        self.x >= 0 && self.y >= 0 && self.x < 200 && self.y < 200
+    /// n行m列の盤面に含まれるか
+    pub fn is_valid(&self, n: usize, m: usize) -> bool {
+        self.x >= 0 && self.y >= 0 && self.y < n as i64 && self.x < m as i64
     }
 
-    pub fn to_idx(&self) -> usize {
-        self.y as usize * N + self.x as usize
+    /// m列の盤面での、行優先の添字
+    pub fn to_idx(self, m: usize) -> usize {
+        self.y as usize * m + self.x as usize
     }
 }
 
+/// あるマスから見て、隣接するとみなすマスの範囲
+///
+/// 水は上下左右にしか流れないので、経路にはFourを使う
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(unused)]
+pub enum Neighborhood {
+    /// 上下左右
+    Four,
+    /// 上下左右と斜め
+    Eight,
+    /// y, xの差がどちらもr以下（Radius(1)はEightと同じ）
+    Radius(i64),
+}
+
+#[allow(unused)]
+impl Neighborhood {
+    const FOUR: [(i64, i64); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+    const EIGHT: [(i64, i64); 8] = [
+        (-1, 0),
+        (1, 0),
+        (0, -1),
+        (0, 1),
+        (-1, -1),
+        (-1, 1),
+        (1, -1),
+        (1, 1),
+    ];
+
+    /// 自分自身を除いた(dy, dx)
+    pub fn offsets(&self) -> Cow<'static, [(i64, i64)]> {
+        match *self {
+            Neighborhood::Four => Cow::Borrowed(&Neighborhood::FOUR),
+            Neighborhood::Eight => Cow::Borrowed(&Neighborhood::EIGHT),
+            Neighborhood::Radius(r) => Cow::Owned(
+                (-r..=r)
+                    .flat_map(|dy| (-r..=r).map(move |dx| (dy, dx)))
+                    .filter(|&d| d != (0, 0))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// posの近傍のうち、n行m列の盤面に含まれるマス
+    pub fn around(&self, pos: &Pos, n: usize, m: usize) -> impl Iterator<Item = Pos> {
+        let (offsets, pos) = (self.offsets(), *pos);
+        (0..offsets.len())
+            .map(move |i| Pos {
+                y: pos.y + offsets[i].0,
+                x: pos.x + offsets[i].1,
+            })
+            .filter(move |np| np.is_valid(n, m))
+    }
+}
+
+/// n行m列の盤面の各マスに値を持つ
+///
+/// 盤面の外を指すPosで読み書きすると、debugビルドではpanicする
 #[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct Vec2d<T> {
@@ -85,6 +200,72 @@ pub struct Vec2d<T> {
     m: usize,
 }
 
+#[allow(unused)]
+impl<T> Vec2d<T> {
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn m(&self) -> usize {
+        self.m
+    }
+
+    /// posが盤面に含まれるか
+    pub fn contains(&self, pos: &Pos) -> bool {
+        pos.is_valid(self.n, self.m)
+    }
+
+    pub fn get_mut(&mut self, pos: &Pos) -> &mut T {
+        let idx = self.idx(pos);
+        &mut self.vec[idx]
+    }
+
+    /// 全てのマスを、行優先で位置と一緒に返す
+    pub fn iter(&self) -> impl Iterator<Item = (Pos, &T)> + '_ {
+        let m = self.m;
+        self.vec.iter().enumerate().map(move |(i, v)| {
+            let pos = Pos {
+                y: (i / m) as i64,
+                x: (i % m) as i64,
+            };
+            (pos, v)
+        })
+    }
+
+    /// posに上下左右で隣接する、盤面に含まれるマス
+    pub fn neighbors(&self, pos: &Pos) -> impl Iterator<Item = Pos> {
+        self.neighbors_in(pos, Neighborhood::Four)
+    }
+
+    /// posのneighborhoodに含まれる、盤面に含まれるマス
+    pub fn neighbors_in(
+        &self,
+        pos: &Pos,
+        neighborhood: Neighborhood,
+    ) -> impl Iterator<Item = Pos> {
+        neighborhood.around(pos, self.n, self.m)
+    }
+
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> Vec2d<U> {
+        Vec2d {
+            vec: self.vec.iter().map(f).collect(),
+            n: self.n,
+            m: self.m,
+        }
+    }
+
+    fn idx(&self, pos: &Pos) -> usize {
+        debug_assert!(
+            self.contains(pos),
+            "{:?} is out of {}x{} Vec2d",
+            pos,
+            self.n,
+            self.m
+        );
+        pos.to_idx(self.m)
+    }
+}
+
 #[allow(unused)]
 impl<T> Vec2d<T>
 where
@@ -99,10 +280,29 @@ where
     }
 
     pub fn get(&self, pos: &Pos) -> T {
-        self.vec[pos.to_idx()]
+        self.vec[self.idx(pos)]
     }
 
     pub fn set(&mut self, pos: &Pos, val: T) {
-        self.vec[pos.to_idx()] = val
+        let idx = self.idx(pos);
+        self.vec[idx] = val
+    }
+
+    pub fn fill(&mut self, val: T) {
+        self.vec.fill(val);
+    }
+}
+
+impl<T> Index<Pos> for Vec2d<T> {
+    type Output = T;
+
+    fn index(&self, pos: Pos) -> &T {
+        &self.vec[self.idx(&pos)]
+    }
+}
+
+impl<T> IndexMut<Pos> for Vec2d<T> {
+    fn index_mut(&mut self, pos: Pos) -> &mut T {
+        self.get_mut(&pos)
     }
 }
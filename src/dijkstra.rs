@@ -0,0 +1,196 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::def::*;
+
+/// マスに入るコストを重みとした、使い回せるダイクストラ法
+///
+/// neighborhoodに含まれるマスへ移動できるとする
+/// 配列は世代番号で初期化を省くので、何度呼んでもn * mの確保と初期化は最初の1回だけになる
+#[derive(Debug)]
+pub struct Dijkstra {
+    n: usize,
+    m: usize,
+    neighborhood: Neighborhood,
+    generation: u32,
+    visited: Vec<u32>,
+    dist: Vec<i64>,
+    par: Vec<Option<Pos>>,
+    heap: BinaryHeap<(Reverse<i64>, Reverse<i64>, Pos)>,
+}
+
+impl Dijkstra {
+    pub fn new(n: usize, m: usize, neighborhood: Neighborhood) -> Dijkstra {
+        Dijkstra {
+            n,
+            m,
+            neighborhood,
+            generation: 0,
+            visited: vec![0; n * m],
+            dist: vec![INF; n * m],
+            par: vec![None; n * m],
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// startsから探索し、is_targetを満たすマスが最初に確定した時点でそのマスを返す
+    ///
+    /// cost(p)はpに入るコスト、heuristic(p)はpから目的のマスまでのコストの下界で、
+    /// 無矛盾（隣のマスとの差がコスト以下）でないといけない。常に0ならダイクストラ法になる。
+    /// 距離の下界がupperを超えるマスは探索しない
+    pub fn run(
+        &mut self,
+        starts: &[Pos],
+        upper: i64,
+        cost: impl Fn(&Pos) -> i64,
+        mut is_target: impl FnMut(&Pos) -> bool,
+        heuristic: impl Fn(&Pos) -> i64,
+    ) -> Option<Pos> {
+        self.next_generation();
+        for s in starts.iter() {
+            self.update(s, 0, None);
+            self.heap.push((Reverse(heuristic(s)), Reverse(0), *s));
+        }
+
+        while let Some((_, Reverse(d), p)) = self.heap.pop() {
+            if self.dist(&p) < d {
+                continue;
+            }
+            if is_target(&p) {
+                self.heap.clear();
+                return Some(p);
+            }
+            for np in self.neighborhood.around(&p, self.n, self.m) {
+                let nd = d + cost(&np);
+                let f = nd + heuristic(&np);
+                if f > upper || self.dist(&np) <= nd {
+                    continue;
+                }
+                self.update(&np, nd, Some(p));
+                self.heap.push((Reverse(f), Reverse(nd), np));
+            }
+        }
+        None
+    }
+
+    /// 直前のrunでのpまでの距離（届いていなければINF）
+    pub fn dist(&self, p: &Pos) -> i64 {
+        let idx = self.idx(p);
+        if self.visited[idx] == self.generation {
+            self.dist[idx]
+        } else {
+            INF
+        }
+    }
+
+    /// 直前のrunでの、始点からpまでに通るマス（両端を含む）
+    pub fn path_to(&self, p: &Pos) -> Vec<Pos> {
+        let mut cur = *p;
+        let mut path = vec![cur];
+        while let Some(parent) = self.par[self.idx(&cur)] {
+            path.push(parent);
+            cur = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    fn next_generation(&mut self) {
+        self.heap.clear();
+        self.generation += 1;
+        if self.generation == u32::MAX {
+            self.visited.fill(0);
+            self.generation = 1;
+        }
+    }
+
+    fn update(&mut self, p: &Pos, d: i64, par: Option<Pos>) {
+        let idx = self.idx(p);
+        self.visited[idx] = self.generation;
+        self.dist[idx] = d;
+        self.par[idx] = par;
+    }
+
+    fn idx(&self, p: &Pos) -> usize {
+        p.to_idx(self.m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_weight(n: usize, m: usize, seed: u64) -> Vec2d<i64> {
+        let mut s = seed * 2 + 1;
+        let mut weight = Vec2d::new(n, m, 0);
+        for y in 0..n as i64 {
+            for x in 0..m as i64 {
+                s ^= s << 13;
+                s ^= s >> 7;
+                s ^= s << 17;
+                weight.set(&Pos { y, x }, 5 + (s % 100) as i64);
+            }
+        }
+        weight
+    }
+
+    #[test]
+    fn a_star_finds_the_same_distance_as_dijkstra() {
+        let (n, m) = (20, 30);
+        let mut dijkstra = Dijkstra::new(n, m, Neighborhood::Four);
+        let mut a_star = Dijkstra::new(n, m, Neighborhood::Four);
+        for seed in 0..20 {
+            let weight = random_weight(n, m, seed);
+            let start = Pos {
+                y: seed as i64 % 7,
+                x: seed as i64 % 11,
+            };
+            let goal = Pos {
+                y: n as i64 - 1 - seed as i64 % 5,
+                x: m as i64 - 1 - seed as i64 % 3,
+            };
+            let cost = |p: &Pos| weight.get(p);
+            // どのマスにも5以上かかるので、マンハッタン距離の5倍は無矛盾な下界
+            let heuristic = |p: &Pos| 5 * p.manhattan_dist(&goal);
+
+            dijkstra.run(&[start], INF, cost, |_| false, |_| 0);
+            let found = a_star.run(&[start], INF, cost, |p| *p == goal, heuristic);
+            assert_eq!(found, Some(goal));
+            assert_eq!(a_star.dist(&goal), dijkstra.dist(&goal));
+
+            let path = a_star.path_to(&goal);
+            assert_eq!((path[0], *path.last().unwrap()), (start, goal));
+            let path_cost: i64 = path[1..].iter().map(cost).sum();
+            assert_eq!(path_cost, dijkstra.dist(&goal));
+
+            // 上界より遠ければ見つからない
+            let upper = dijkstra.dist(&goal) - 1;
+            assert_eq!(
+                a_star.run(&[start], upper, cost, |p| *p == goal, heuristic),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn stops_at_the_nearest_target() {
+        let (n, m) = (15, 15);
+        let weight = random_weight(n, m, 3);
+        let targets = [
+            Pos { y: 0, x: 14 },
+            Pos { y: 14, x: 0 },
+            Pos { y: 14, x: 14 },
+        ];
+        let start = Pos { y: 3, x: 4 };
+        let mut dijkstra = Dijkstra::new(n, m, Neighborhood::Four);
+        dijkstra.run(&[start], INF, |p| weight.get(p), |_| false, |_| 0);
+        let nearest = *targets.iter().min_by_key(|t| dijkstra.dist(t)).unwrap();
+        let found = dijkstra.run(
+            &[start],
+            INF,
+            |p| weight.get(p),
+            |p| targets.contains(p),
+            |_| 0,
+        );
+        assert_eq!(found, Some(nearest));
+    }
+}
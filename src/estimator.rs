@@ -0,0 +1,631 @@
+use crate::{def::*, params::Params};
+
+/// log2(頑丈度)の観測
+#[derive(Clone, Copy, Debug)]
+struct Observation {
+    pos: Pos,
+    /// 元の区間
+    interval: Interval,
+    /// 区間から補完した値
+    value: f64,
+    /// 観測ノイズの分散
+    noise: f64,
+    lower: f64,
+    upper: Option<f64>,
+}
+
+impl Observation {
+    fn from_interval(interval: &Interval) -> Observation {
+        let lower = (i64::max(interval.lower, S_MIN) as f64).log2();
+        match interval.upper {
+            // 区間内で一様に分布しているとみなす
+            Some(upper) => {
+                let upper = f64::max((upper as f64).log2(), lower);
+                Observation {
+                    pos: interval.pos,
+                    interval: *interval,
+                    value: (lower + upper) / 2.,
+                    noise: (upper - lower) * (upper - lower) / 12.,
+                    lower,
+                    upper: Some(upper),
+                }
+            }
+            // 初期値は下限より少し大きい値とし、不確かさを大きくしておく
+            None => Observation {
+                pos: interval.pos,
+                interval: *interval,
+                value: lower + 0.5,
+                noise: 1.,
+                lower,
+                upper: None,
+            },
+        }
+    }
+
+    /// 予測分布を、この観測の区間で切断する
+    fn condition(&self, e: &Estimate) -> Estimate {
+        let (mean, var) = truncated_normal_moments(e.mean, e.std, self.lower, self.upper);
+        Estimate {
+            mean,
+            std: var.sqrt(),
+        }
+    }
+
+    /// intervalから作った観測か
+    fn is_from(&self, interval: &Interval) -> bool {
+        self.interval.lower == interval.lower && self.interval.upper == interval.upper
+    }
+}
+
+/// log2(頑丈度)の予測分布
+#[derive(Clone, Copy, Debug)]
+pub struct Estimate {
+    pub mean: f64,
+    pub std: f64,
+}
+
+/// 全マスの予測分布（観測したマスは、その区間で切断する）
+pub struct HardnessField {
+    mean: Vec2d<f64>,
+    std: Vec2d<f64>,
+    /// 補完した観測と、マスごとの観測の添字
+    observations: Vec<Observation>,
+    observed: Vec2d<Option<usize>>,
+    /// observationsを近い順に探すためのバケット
+    index: SpatialIndex,
+    /// observationsの値の和（事前分布の平均に使う）
+    value_sum: f64,
+}
+
+impl HardnessField {
+    pub fn get(&self, pos: &Pos) -> Estimate {
+        Estimate {
+            mean: self.mean.get(pos),
+            std: self.std.get(pos),
+        }
+    }
+}
+
+/// log2(頑丈度)をガウス過程回帰（クリギング）で予測する
+///
+/// 各観測は頑丈度の区間として扱い、他の観測からの予測を区間で切断した正規分布の平均で補完する
+pub struct Estimator {
+    /// カーネルの長さスケール
+    pub length_scale: f64,
+    /// カーネルの分散
+    pub signal_var: f64,
+    /// 全ての観測に加えるノイズの分散
+    pub nugget: f64,
+    /// 観測を探す範囲
+    pub search_radius: f64,
+    /// 観測がない時の頑丈度
+    pub default_hardness: f64,
+    /// 同じ観測で同時に予測するブロックの一辺
+    pub block: usize,
+    /// 予測に使う観測の最大数
+    pub max_neighbors: usize,
+    /// 下限付きの観測の補完に使う観測の最大数
+    pub impute_neighbors: usize,
+}
+
+impl Estimator {
+    pub fn new(params: &Params) -> Estimator {
+        Estimator {
+            length_scale: params.length_scale,
+            signal_var: params.signal_var,
+            nugget: params.nugget,
+            search_radius: params.estimate_radius,
+            default_hardness: params.default_hardness,
+            block: params.estimate_block,
+            max_neighbors: params.estimate_neighbors,
+            impute_neighbors: params.impute_neighbors,
+        }
+    }
+
+    /// n行m列の盤面の全マスを予測する
+    pub fn estimate(&self, intervals: &[Interval], n: usize, m: usize) -> HardnessField {
+        let observations = self.impute(intervals, n, m);
+        let value_sum = observations.iter().map(|o| o.value).sum::<f64>();
+        let prior_mean = self.prior_mean(value_sum, observations.len());
+
+        let mut mean = Vec2d::new(n, m, prior_mean);
+        let mut std = Vec2d::new(n, m, self.signal_var.sqrt());
+        let index = SpatialIndex::new(&observations, n, m);
+
+        let block = self.block;
+        for by in (0..n).step_by(block) {
+            for bx in (0..m).step_by(block) {
+                let center = Pos {
+                    y: (by + block / 2).min(n - 1) as i64,
+                    x: (bx + block / 2).min(m - 1) as i64,
+                };
+                let neighbors = index.nearest(
+                    &observations,
+                    &center,
+                    None,
+                    self.search_radius,
+                    self.max_neighbors,
+                );
+                if neighbors.is_empty() {
+                    continue;
+                }
+                let model = self.fit(&observations, &neighbors, prior_mean);
+                for y in by..usize::min(by + block, n) {
+                    for x in bx..usize::min(bx + block, m) {
+                        let p = Pos {
+                            y: y as i64,
+                            x: x as i64,
+                        };
+                        let e = model.predict(self, &observations, &p);
+                        mean.set(&p, e.mean);
+                        std.set(&p, e.std);
+                    }
+                }
+            }
+        }
+
+        let mut observed = Vec2d::new(n, m, None);
+        for (i, o) in observations.iter().enumerate() {
+            observed.set(&o.pos, Some(i));
+            let e = o.condition(&Estimate {
+                mean: mean.get(&o.pos),
+                std: std.get(&o.pos),
+            });
+            mean.set(&o.pos, e.mean);
+            std.set(&o.pos, e.std);
+        }
+        HardnessField {
+            mean,
+            std,
+            observations,
+            observed,
+            index,
+            value_sum,
+        }
+    }
+
+    /// fieldを予測した後に増えた・変わった区間を、それまでの観測からの予測を区間で切断した平均で補完して加える
+    ///
+    /// 掘削中に観測が1つ増えるごとに呼ぶので、全ての観測を補完し直すことはせず、
+    /// 観測を探すバケットも作り直さずに加えていく
+    pub fn update(&self, field: &mut HardnessField, intervals: &[Interval]) {
+        for interval in intervals.iter() {
+            let existing = field.observed.get(&interval.pos);
+            if existing.is_some_and(|i| field.observations[i].is_from(interval)) {
+                continue;
+            }
+            let mut o = Observation::from_interval(interval);
+            let e = self.predict_from(field, &o.pos, existing);
+            (o.value, o.noise) = truncated_normal_moments(e.mean, e.std, o.lower, o.upper);
+            field.value_sum += o.value;
+            match existing {
+                Some(i) => {
+                    field.value_sum -= field.observations[i].value;
+                    field.observations[i] = o;
+                }
+                None => {
+                    let i = field.observations.len();
+                    field.observed.set(&o.pos, Some(i));
+                    field.index.insert(i, &o.pos);
+                    field.observations.push(o);
+                }
+            }
+        }
+    }
+
+    /// posだけを、fieldの補完した観測からその場で予測する
+    pub fn predict(&self, field: &HardnessField, pos: &Pos) -> Estimate {
+        let e = self.predict_from(field, pos, None);
+        match field.observed.get(pos) {
+            Some(i) => field.observations[i].condition(&e),
+            None => e,
+        }
+    }
+
+    /// posを、exclude以外の近傍の観測から予測する
+    fn predict_from(&self, field: &HardnessField, pos: &Pos, exclude: Option<usize>) -> Estimate {
+        let observations = &field.observations;
+        let prior_mean = self.prior_mean(field.value_sum, observations.len());
+        let neighbors = field.index.nearest(
+            observations,
+            pos,
+            exclude,
+            self.search_radius,
+            self.max_neighbors,
+        );
+        if neighbors.is_empty() {
+            return Estimate {
+                mean: prior_mean,
+                std: self.signal_var.sqrt(),
+            };
+        }
+        self.fit(observations, &neighbors, prior_mean)
+            .predict(self, observations, pos)
+    }
+
+    /// 値の和がvalue_sumのcount個の観測から決める、事前分布の平均
+    fn prior_mean(&self, value_sum: f64, count: usize) -> f64 {
+        if count == 0 {
+            self.default_hardness.log2()
+        } else {
+            value_sum / count as f64
+        }
+    }
+
+    /// 各観測の値を、他の観測から予測した分布を区間で切断した平均で置き換える
+    fn impute(&self, intervals: &[Interval], n: usize, m: usize) -> Vec<Observation> {
+        let mut imputed: Vec<Observation> =
+            intervals.iter().map(Observation::from_interval).collect();
+        let index = SpatialIndex::new(&imputed, n, m);
+
+        for _ in 0..2 {
+            let prior_mean =
+                imputed.iter().map(|o| o.value).sum::<f64>() / usize::max(1, imputed.len()) as f64;
+            let mut next = imputed.clone();
+            for (i, o) in imputed.iter().enumerate() {
+                let neighbors = index.nearest(
+                    &imputed,
+                    &o.pos,
+                    Some(i),
+                    self.search_radius,
+                    self.impute_neighbors,
+                );
+                let e = if neighbors.is_empty() {
+                    Estimate {
+                        mean: prior_mean,
+                        std: self.signal_var.sqrt(),
+                    }
+                } else {
+                    self.fit(&imputed, &neighbors, prior_mean)
+                        .predict(self, &imputed, &o.pos)
+                };
+                let (m, v) = truncated_normal_moments(e.mean, e.std, o.lower, o.upper);
+                next[i].value = m;
+                next[i].noise = v;
+            }
+            imputed = next;
+        }
+        imputed
+    }
+
+    fn fit(&self, observations: &[Observation], neighbors: &[usize], prior_mean: f64) -> Model {
+        let k = neighbors.len();
+        let mut l = vec![0.; k * k];
+        for i in 0..k {
+            for j in 0..=i {
+                let (a, b) = (&observations[neighbors[i]], &observations[neighbors[j]]);
+                l[i * k + j] = self.kernel(&a.pos, &b.pos);
+            }
+            l[i * k + i] += observations[neighbors[i]].noise + self.nugget;
+        }
+        cholesky(&mut l, k);
+
+        let mut alpha: Vec<f64> = neighbors
+            .iter()
+            .map(|&i| observations[i].value - prior_mean)
+            .collect();
+        solve_lower(&l, k, &mut alpha);
+        solve_upper(&l, k, &mut alpha);
+
+        Model {
+            neighbors: neighbors.to_vec(),
+            l,
+            alpha,
+            prior_mean,
+        }
+    }
+
+    /// 事前分布でのaとbの相関係数
+    pub fn correlation(&self, a: &Pos, b: &Pos) -> f64 {
+        self.kernel(a, b) / self.signal_var
+    }
+
+    fn kernel(&self, a: &Pos, b: &Pos) -> f64 {
+        let d2 = ((a.y - b.y) * (a.y - b.y) + (a.x - b.x) * (a.x - b.x)) as f64;
+        self.signal_var * (-d2 / (2. * self.length_scale * self.length_scale)).exp()
+    }
+}
+
+/// 近傍の観測で学習したガウス過程
+struct Model {
+    neighbors: Vec<usize>,
+    /// 共分散行列のコレスキー分解
+    l: Vec<f64>,
+    alpha: Vec<f64>,
+    prior_mean: f64,
+}
+
+impl Model {
+    fn predict(&self, estimator: &Estimator, observations: &[Observation], p: &Pos) -> Estimate {
+        let k = self.neighbors.len();
+        let mut v: Vec<f64> = self
+            .neighbors
+            .iter()
+            .map(|&i| estimator.kernel(p, &observations[i].pos))
+            .collect();
+        let mean = self.prior_mean
+            + v.iter()
+                .zip(self.alpha.iter())
+                .map(|(a, b)| a * b)
+                .sum::<f64>();
+        solve_lower(&self.l, k, &mut v);
+        let var = estimator.signal_var - v.iter().map(|a| a * a).sum::<f64>();
+        Estimate {
+            mean,
+            std: f64::max(var, 1e-6).sqrt(),
+        }
+    }
+}
+
+/// 観測をバケットに分けて、近い観測を探す
+struct SpatialIndex {
+    buckets: Vec<Vec<usize>>,
+    /// バケットの行数と列数
+    rows: usize,
+    cols: usize,
+}
+
+impl SpatialIndex {
+    const BUCKET: usize = 10;
+
+    fn new(observations: &[Observation], n: usize, m: usize) -> SpatialIndex {
+        let rows = n.div_ceil(SpatialIndex::BUCKET);
+        let cols = m.div_ceil(SpatialIndex::BUCKET);
+        let mut index = SpatialIndex {
+            buckets: vec![vec![]; rows * cols],
+            rows,
+            cols,
+        };
+        for (i, o) in observations.iter().enumerate() {
+            index.insert(i, &o.pos);
+        }
+        index
+    }
+
+    /// i番目の観測を加える
+    fn insert(&mut self, i: usize, pos: &Pos) {
+        let (by, bx) = (
+            pos.y as usize / SpatialIndex::BUCKET,
+            pos.x as usize / SpatialIndex::BUCKET,
+        );
+        self.buckets[by * self.cols + bx].push(i);
+    }
+
+    /// centerからradius以内の観測を、近い順に最大limit個返す
+    fn nearest(
+        &self,
+        observations: &[Observation],
+        center: &Pos,
+        exclude: Option<usize>,
+        radius: f64,
+        limit: usize,
+    ) -> Vec<usize> {
+        // バケットを1マスとみなした盤面で、半径に収まるバケットを見る
+        let r = (radius as usize).div_ceil(SpatialIndex::BUCKET) as i64;
+        let bucket = Pos {
+            y: center.y / SpatialIndex::BUCKET as i64,
+            x: center.x / SpatialIndex::BUCKET as i64,
+        };
+        let around = Neighborhood::Radius(r).around(&bucket, self.rows, self.cols);
+        let mut candidates = vec![];
+        for b in std::iter::once(bucket).chain(around) {
+            for &i in self.buckets[b.to_idx(self.cols)].iter() {
+                let d = center.euclid_dist(&observations[i].pos);
+                if Some(i) != exclude && d <= radius {
+                    candidates.push((d, i));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        candidates.truncate(limit);
+        candidates.into_iter().map(|(_, i)| i).collect()
+    }
+}
+
+/// N(mu, sigma^2)を(lower, upper]に切断した分布の平均と分散
+fn truncated_normal_moments(mu: f64, sigma: f64, lower: f64, upper: Option<f64>) -> (f64, f64) {
+    let a = (lower - mu) / sigma;
+    let Some(upper) = upper else {
+        // 逆ミルズ比 φ(a) / (1 - Φ(a))
+        let lambda = if a > 6. {
+            a + 1. / a
+        } else {
+            normal_pdf(a) / (1. - normal_cdf(a))
+        };
+        let mean = mu + sigma * lambda;
+        let var = sigma * sigma * f64::max(1. + a * lambda - lambda * lambda, 1e-3);
+        return (f64::max(mean, lower), var);
+    };
+
+    let b = (upper - mu) / sigma;
+    let z = normal_cdf(b) - normal_cdf(a);
+    let width = upper - lower;
+    if z < 1e-9 {
+        // 予測分布が区間から大きく外れている場合は、区間の予測に近い側に寄せる
+        return (mu.clamp(lower, upper), width * width / 12.);
+    }
+    let (pa, pb) = (normal_pdf(a), normal_pdf(b));
+    let lambda = (pa - pb) / z;
+    let mean = mu + sigma * lambda;
+    let var = sigma * sigma * (1. + (a * pa - b * pb) / z - lambda * lambda);
+    (
+        mean.clamp(lower, upper),
+        var.clamp(1e-4, f64::max(width * width / 12., 1e-4)),
+    )
+}
+
+fn normal_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2. * std::f64::consts::PI).sqrt()
+}
+
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1. + erf(x / std::f64::consts::SQRT_2))
+}
+
+/// Abramowitz and Stegun 7.1.26
+fn erf(x: f64) -> f64 {
+    let sign = if x < 0. { -1. } else { 1. };
+    let x = x.abs();
+    let t = 1. / (1. + 0.3275911 * x);
+    let y = 1.
+        - (((((1.061405429 * t - 1.453152027) * t) + 1.421413741) * t - 0.284496736) * t
+            + 0.254829592)
+            * t
+            * (-x * x).exp();
+    sign * y
+}
+
+/// 対称正定値行列aを、下三角行列lに分解する（a = l l^T）
+fn cholesky(a: &mut [f64], k: usize) {
+    for j in 0..k {
+        let mut d = a[j * k + j];
+        for t in 0..j {
+            d -= a[j * k + t] * a[j * k + t];
+        }
+        let d = f64::max(d, 1e-9).sqrt();
+        a[j * k + j] = d;
+        for i in j + 1..k {
+            let mut s = a[i * k + j];
+            for t in 0..j {
+                s -= a[i * k + t] * a[j * k + t];
+            }
+            a[i * k + j] = s / d;
+        }
+        for i in 0..j {
+            a[i * k + j] = 0.;
+        }
+    }
+}
+
+/// l x = bを解く
+fn solve_lower(l: &[f64], k: usize, b: &mut [f64]) {
+    for i in 0..k {
+        let mut s = b[i];
+        for j in 0..i {
+            s -= l[i * k + j] * b[j];
+        }
+        b[i] = s / l[i * k + i];
+    }
+}
+
+/// l^T x = bを解く
+fn solve_upper(l: &[f64], k: usize, b: &mut [f64]) {
+    for i in (0..k).rev() {
+        let mut s = b[i];
+        for j in i + 1..k {
+            s -= l[j * k + i] * b[j];
+        }
+        b[i] = s / l[i * k + i];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_normal_moments_matches_closed_form() {
+        // 半正規分布: 平均sqrt(2/π)、分散1 - 2/π
+        let (mean, var) = truncated_normal_moments(0., 1., 0., None);
+        assert!((mean - 0.7979).abs() < 1e-3, "{}", mean);
+        assert!((var - 0.3634).abs() < 1e-3, "{}", var);
+
+        // (-1, 1]: 平均0、分散1 - 2φ(1) / (Φ(1) - Φ(-1))
+        let (mean, var) = truncated_normal_moments(0., 1., -1., Some(1.));
+        assert!(mean.abs() < 1e-3, "{}", mean);
+        assert!((var - 0.2911).abs() < 1e-3, "{}", var);
+
+        // 平行移動と拡大に対して整合する
+        let (mean, var) = truncated_normal_moments(5., 2., 5., None);
+        assert!((mean - (5. + 2. * 0.7979)).abs() < 2e-3, "{}", mean);
+        assert!((var - 4. * 0.3634).abs() < 4e-3, "{}", var);
+    }
+
+    #[test]
+    fn truncated_normal_moments_stays_in_interval() {
+        // 予測分布が区間から大きく外れていても、区間内に収まる
+        let (mean, var) = truncated_normal_moments(0., 0.5, 10., Some(11.));
+        assert!((10. ..=11.).contains(&mean));
+        assert!(var > 0.);
+        let (mean, _) = truncated_normal_moments(0., 0.5, 10., None);
+        assert!(mean >= 10.);
+    }
+
+    fn broken(y: i64, x: i64, lower: i64, upper: i64) -> Interval {
+        Interval {
+            pos: Pos { y, x },
+            lower,
+            upper: Some(upper),
+        }
+    }
+
+    fn intervals() -> Vec<Interval> {
+        vec![
+            broken(5, 5, 64, 128),
+            broken(5, 15, 1000, 1200),
+            broken(15, 5, 200, 300),
+            broken(15, 15, 400, 500),
+        ]
+    }
+
+    #[test]
+    fn estimate_is_near_broken_interval() {
+        let estimator = Estimator::new(&Params::default());
+        let field = estimator.estimate(&intervals(), 40, 40);
+        // 壊れたマスとその隣は、区間の近くに予測する
+        for p in [Pos { y: 5, x: 5 }, Pos { y: 5, x: 6 }] {
+            let e = field.get(&p);
+            assert!((5.5..=7.5).contains(&e.mean), "{:?}: {:?}", p, e);
+        }
+        let e = field.get(&Pos { y: 5, x: 15 });
+        assert!((9.5..=10.7).contains(&e.mean), "{:?}", e);
+    }
+
+    #[test]
+    fn estimate_is_certain_near_observations() {
+        let estimator = Estimator::new(&Params::default());
+        let field = estimator.estimate(&intervals(), 40, 40);
+        let near = field.get(&Pos { y: 5, x: 5 });
+        let far = field.get(&Pos { y: 39, x: 39 });
+        assert!(near.std < far.std, "{:?}, {:?}", near, far);
+    }
+
+    #[test]
+    fn unbroken_probe_raises_estimate_above_damage() {
+        let estimator = Estimator::new(&Params::default());
+        let mut intervals = intervals();
+        intervals.push(Interval {
+            pos: Pos { y: 10, x: 10 },
+            lower: 2000,
+            upper: None,
+        });
+        let field = estimator.estimate(&intervals, 40, 40);
+        let e = field.get(&Pos { y: 10, x: 10 });
+        assert!(e.mean > 2000f64.log2(), "{:?}", e);
+    }
+
+    #[test]
+    fn update_matches_fresh_estimate() {
+        let estimator = Estimator::new(&Params::default());
+        let mut intervals = intervals();
+        let mut field = estimator.estimate(&intervals, 40, 40);
+        intervals.push(broken(10, 10, 300, 400));
+        estimator.update(&mut field, &intervals);
+
+        let p = Pos { y: 10, x: 10 };
+        let updated = estimator.predict(&field, &p);
+        let fresh = estimator.estimate(&intervals, 40, 40).get(&p);
+        assert!(
+            (updated.mean - fresh.mean).abs() < 0.2,
+            "{:?}, {:?}",
+            updated,
+            fresh
+        );
+        assert!(
+            (updated.std - fresh.std).abs() < 0.2,
+            "{:?}, {:?}",
+            updated,
+            fresh
+        );
+    }
+}
@@ -1,163 +1,233 @@
-use std::{
-    cmp::Reverse,
-    collections::{BinaryHeap, HashSet},
-    fs::File,
-    io::Write,
-};
+use std::{collections::VecDeque, fs::File, io::Write};
 
-use crate::def::*;
-
-const DELTA: [(i64, i64); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+use crate::{def::*, dijkstra::Dijkstra, util::UnionFind};
 
 #[derive(Debug)]
 pub struct Grid {
+    /// 行数
+    pub n: usize,
+    /// 列数
+    pub m: usize,
     pub total_score: i64,
     pub estimated_weight: Vec2d<i64>,
     pub is_used: Vec2d<bool>,
     pub house: Vec<Pos>,
     pub source: Vec<Pos>,
+    /// 使っているマスと、used_cellsでの位置（使っていなければusize::MAX）
+    used_cells: Vec<Pos>,
+    used_index: Vec2d<usize>,
+    /// 使っているマスの連結成分（n * m番目の頂点は全ての水源と繋がっている）
+    connectivity: UnionFind,
+    /// 前回作り直してからconnectivityで繋いだ頂点
+    connected_nodes: Vec<usize>,
+    /// マスを外した後で、connectivityを作り直す必要があるか
+    is_connectivity_stale: bool,
+    dijkstra: Dijkstra,
 }
 
 impl Grid {
+    pub fn new(
+        estimated_weight: Vec2d<i64>,
+        is_used: Vec2d<bool>,
+        house: Vec<Pos>,
+        source: Vec<Pos>,
+    ) -> Grid {
+        let (n, m) = (estimated_weight.n(), estimated_weight.m());
+        let mut used_cells = vec![];
+        let mut used_index = Vec2d::new(n, m, usize::MAX);
+        for (p, _) in is_used.iter().filter(|(_, &is_used)| is_used) {
+            used_index.set(&p, used_cells.len());
+            used_cells.push(p);
+        }
+        Grid {
+            n,
+            m,
+            total_score: 0,
+            estimated_weight,
+            is_used,
+            house,
+            source,
+            used_cells,
+            used_index,
+            connectivity: UnionFind::new(n * m + 1),
+            connected_nodes: vec![],
+            is_connectivity_stale: true,
+            dijkstra: Dijkstra::new(n, m, Neighborhood::Four),
+        }
+    }
+
+    /// startから、sourceのうち一番繋げるまでのコストが小さい水源までの経路とコスト
+    /// コストがupperを超える場合はNoneを返す
     pub fn find_path_to_nearest_source(
-        &self,
+        &mut self,
         start: &Pos,
         upper: i64,
-        source: &Vec<Pos>,
-        c: i64,
-    ) -> (Vec<Pos>, i64) {
-        let (dist, par) = self.dijkstra(start, upper, c);
-
-        let mut best_source_pos = None;
-        // 一番繋げるまでの距離が近い水源を探す
-        for s_pos in source.iter() {
-            if best_source_pos.is_none() || dist.get(s_pos) < dist.get(best_source_pos.unwrap()) {
-                best_source_pos = Some(s_pos);
-            }
-        }
+        source: &[Pos],
+    ) -> Option<(Vec<Pos>, i64)> {
+        let (is_used, estimated_weight) = (&self.is_used, &self.estimated_weight);
+        let best_source_pos = self.dijkstra.run(
+            &[*start],
+            upper,
+            |p| cell_cost(is_used, estimated_weight, p),
+            |p| source.contains(p),
+            |_| 0,
+        )?;
+        let path = self.dijkstra.path_to(&best_source_pos);
+        Some((path, self.dijkstra.dist(&best_source_pos)))
+    }
 
-        // h -> best_source_posまでに通る辺を復元する
-        let mut cur = best_source_pos.unwrap().clone();
-        let mut path = vec![cur.clone()];
-        while &cur != start {
-            let parent = par.get(&cur).unwrap();
-            path.push(parent);
-            cur = parent;
+    /// startから、水に繋がっているマス（水源と、水源に繋がっている使っているマス）の
+    /// うち一番繋げるまでのコストが小さいマスまでの経路（両端を含む）と、合流先のマス
+    /// コストがupperを超える場合はNoneを返す
+    pub fn find_path_to_water(&mut self, start: &Pos, upper: i64) -> Option<(Vec<Pos>, Pos)> {
+        if self.is_connectivity_stale {
+            self.rebuild_connectivity();
         }
-        path.reverse();
-        (path, dist.get(&best_source_pos.unwrap()))
+        let (water, m) = (self.connectivity.find(self.water_node()), self.m);
+        let (is_used, estimated_weight, connectivity) = (
+            &self.is_used,
+            &self.estimated_weight,
+            &mut self.connectivity,
+        );
+        let attach = self.dijkstra.run(
+            &[*start],
+            upper,
+            |p| cell_cost(is_used, estimated_weight, p),
+            |p| connectivity.find(p.to_idx(m)) == water,
+            |_| 0,
+        )?;
+        Some((self.dijkstra.path_to(&attach), attach))
     }
 
+    /// 使っているマスだけを通る、startから最も近い水源までの最短路
     pub fn find_current_path_to_source(&self, start: &Pos) -> Option<(Vec<Pos>, i64)> {
-        fn dfs(
-            p: &Pos,
-            par: &Pos,
-            st: &mut Vec<Pos>,
-            seen: &mut HashSet<Pos>,
-            grid: &Grid,
-        ) -> bool {
-            if grid.source.contains(p) {
-                return true;
-            }
-            for (dy, dx) in DELTA {
-                let np = Pos {
-                    y: p.y + dy,
-                    x: p.x + dx,
-                };
-                if !np.is_valid() || par == &np {
-                    continue;
+        let mut seen = Vec2d::new(self.n, self.m, false);
+        let mut par = Vec2d::new(self.n, self.m, None);
+        let mut queue = VecDeque::new();
+        seen.set(start, true);
+        queue.push_back(*start);
+
+        while let Some(p) = queue.pop_front() {
+            if self.source.contains(&p) {
+                // p -> startまでに通るマスを復元する
+                let mut path = vec![p];
+                let mut cur = p;
+                while let Some(parent) = par.get(&cur) {
+                    path.push(parent);
+                    cur = parent;
                 }
-                // FIXME: is_usedに常にhouse、sourceが含まれるように修正
-                let is_not_used = !grid.is_used.get(&np) && !grid.source.contains(&np);
-                if is_not_used || seen.contains(&np) {
+                path.reverse();
+                let total_weight = path.iter().map(|p| self.estimated_weight.get(p)).sum();
+                return Some((path, total_weight));
+            }
+            for np in self.is_used.neighbors(&p) {
+                if seen.get(&np) || !self.is_connected_cell(&np) {
                     continue;
                 }
-                st.push(np.clone());
-                seen.insert(np.clone());
-                if dfs(&np, &p, st, seen, grid) {
-                    return true;
-                }
-                st.pop();
-                seen.remove(&np);
+                seen.set(&np, true);
+                par.set(&np, Some(p));
+                queue.push_back(np);
             }
-            false
-        }
-
-        let mut st = vec![];
-        st.push(start.clone());
-        let mut seen = HashSet::new();
-        seen.insert(start.clone());
-
-        if dfs(start, &Pos { y: -1, x: -1 }, &mut st, &mut seen, &self) {
-            let mut total_weight = 0;
-            for p in st.iter() {
-                total_weight += self.estimated_weight.get(p);
-            }
-            Some((st, total_weight))
-        } else {
-            None
         }
+        None
     }
 
-    pub fn find_unconnected_houses(&self) -> Vec<usize> {
+    pub fn find_unconnected_houses(&mut self) -> Vec<usize> {
+        if self.is_connectivity_stale {
+            self.rebuild_connectivity();
+        }
         let mut v = vec![];
-        for (i, h_pos) in self.house.iter().enumerate() {
-            let result = self.find_current_path_to_source(h_pos);
-            if result.is_none() {
+        for i in 0..self.house.len() {
+            let (h, water) = (self.house[i].to_idx(self.m), self.water_node());
+            if !self.connectivity.same(h, water) {
                 v.push(i);
             }
         }
         v
     }
 
-    pub fn set(&mut self, p: &Pos, v: bool) -> bool {
-        if self.is_used.get(&p) == v {
-            return false;
+    /// 使っているマスと水源だけを見て、連結成分を作り直す
+    ///
+    /// 盤面全体ではなく前回繋いだ頂点だけを戻すので、経路の長さに比例する時間で済む
+    fn rebuild_connectivity(&mut self) {
+        for &v in self.connected_nodes.iter() {
+            self.connectivity.reset(v);
         }
-        if self.is_used.get(&p) {
-            self.total_score -= self.estimated_weight.get(&p);
-        } else {
-            self.total_score += self.estimated_weight.get(&p);
+        self.connectivity.reset(self.water_node());
+        self.connected_nodes.clear();
+        for i in 0..self.used_cells.len() {
+            let p = self.used_cells[i];
+            self.connect(&p);
         }
-        self.is_used.set(&p, v);
-        return true;
+        for i in 0..self.source.len() {
+            let s = self.source[i];
+            if !self.is_used.get(&s) {
+                self.connect(&s);
+            }
+        }
+        self.is_connectivity_stale = false;
     }
 
-    fn dijkstra(&self, start: &Pos, upper: i64, c: i64) -> (Vec2d<i64>, Vec2d<Option<Pos>>) {
-        let mut dist = Vec2d::new(N, N, INF);
-        let mut par = Vec2d::new(N, N, None);
+    // FIXME: is_usedに常にhouse、sourceが含まれるように修正
+    fn is_connected_cell(&self, p: &Pos) -> bool {
+        self.is_used.get(p) || self.source.contains(p)
+    }
 
-        let mut heap = BinaryHeap::new();
-        dist.set(start, 0);
-        heap.push((Reverse(0), start.clone()));
+    /// 全ての水源と繋がっている頂点
+    fn water_node(&self) -> usize {
+        self.n * self.m
+    }
 
-        while let Some((Reverse(d), p)) = heap.pop() {
-            if dist.get(&p) < d {
-                continue;
+    /// pと、隣の使っているマス（水源なら水源の頂点）を繋ぐ
+    fn connect(&mut self, p: &Pos) {
+        self.connected_nodes.push(p.to_idx(self.m));
+        if self.source.contains(p) {
+            self.connectivity.unite(p.to_idx(self.m), self.water_node());
+        }
+        for np in self.is_used.neighbors(p) {
+            if self.is_connected_cell(&np) {
+                self.connectivity.unite(p.to_idx(self.m), np.to_idx(self.m));
             }
-            for (dy, dx) in DELTA {
-                let np = Pos {
-                    y: p.y + dy,
-                    x: p.x + dx,
-                };
-                if !np.is_valid() {
-                    continue;
-                }
-                let w = if self.is_used.get(&np) {
-                    0
-                } else {
-                    self.estimated_weight.get(&np) + 2 * c
-                };
-                if dist.get(&np) <= d + w {
-                    continue;
-                }
-                par.set(&np, Some(p.clone()));
-                dist.set(&np, d + w);
-                heap.push((Reverse(d + w), np.clone()));
+        }
+    }
+
+    /// 使っているマス
+    pub fn used_cells(&self) -> &[Pos] {
+        &self.used_cells
+    }
+
+    pub fn set(&mut self, p: &Pos, v: bool) -> bool {
+        if self.is_used.get(p) == v {
+            return false;
+        }
+        if v {
+            self.total_score += self.estimated_weight.get(p);
+            self.used_index.set(p, self.used_cells.len());
+            self.used_cells.push(*p);
+        } else {
+            self.total_score -= self.estimated_weight.get(p);
+            let i = self.used_index.get(p);
+            self.used_cells.swap_remove(i);
+            if let Some(moved) = self.used_cells.get(i) {
+                self.used_index.set(moved, i);
             }
+            self.used_index.set(p, usize::MAX);
         }
+        self.is_used.set(p, v);
+        // マスを加える時は繋ぐだけで良いが、外す時は成分が分かれうるので作り直す
+        if v {
+            if !self.is_connectivity_stale {
+                self.connect(p);
+            }
+        } else {
+            self.is_connectivity_stale = true;
+        }
+        true
+    }
 
-        (dist, par)
+    /// pを経路に加える時のコスト（既に使っているマスは0）
+    pub fn cell_cost(&self, p: &Pos) -> i64 {
+        cell_cost(&self.is_used, &self.estimated_weight, p)
     }
 
     #[allow(unused)]
@@ -166,8 +236,8 @@ impl Grid {
             return;
         }
         let mut file = File::create(output_file).unwrap();
-        for y in 0..N {
-            for x in 0..N {
+        for y in 0..self.n {
+            for x in 0..self.m {
                 if self.is_used.get(&Pos {
                     y: y as i64,
                     x: x as i64,
@@ -180,8 +250,8 @@ impl Grid {
             writeln!(file).unwrap();
         }
 
-        for y in 0..N {
-            for x in 0..N {
+        for y in 0..self.n {
+            for x in 0..self.m {
                 write!(
                     file,
                     "{} ",
@@ -196,3 +266,126 @@ impl Grid {
         }
     }
 }
+
+fn cell_cost(is_used: &Vec2d<bool>, estimated_weight: &Vec2d<i64>, p: &Pos) -> i64 {
+    if is_used.get(p) {
+        0
+    } else {
+        estimated_weight.get(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 使っているマスと水源だけを通って、水源から辿れない家
+    fn unconnected_houses_by_bfs(grid: &Grid) -> Vec<usize> {
+        let mut seen = Vec2d::new(grid.n, grid.m, false);
+        let mut queue: VecDeque<Pos> = grid.source.iter().cloned().collect();
+        for s in grid.source.iter() {
+            seen.set(s, true);
+        }
+        while let Some(p) = queue.pop_front() {
+            for np in seen.neighbors(&p) {
+                if !seen.get(&np) && grid.is_connected_cell(&np) {
+                    seen.set(&np, true);
+                    queue.push_back(np);
+                }
+            }
+        }
+        (0..grid.house.len())
+            .filter(|&i| !seen.get(&grid.house[i]))
+            .collect()
+    }
+
+    #[test]
+    fn current_path_to_source_is_shortest_used_path() {
+        let (n, m) = (5, 5);
+        let source = vec![Pos { y: 0, x: 0 }];
+        let house = vec![Pos { y: 4, x: 4 }, Pos { y: 4, x: 0 }];
+        let mut grid = Grid::new(Vec2d::new(n, m, 1), Vec2d::new(n, m, false), house, source);
+        // 上と右の辺を通る遠回りの経路と、中央を通る近道
+        for i in 0..5 {
+            grid.set(&Pos { y: 0, x: i }, true);
+            grid.set(&Pos { y: i, x: 4 }, true);
+        }
+        for i in 0..5 {
+            grid.set(&Pos { y: 2, x: i }, true);
+        }
+        grid.set(&Pos { y: 1, x: 0 }, true);
+        grid.set(&Pos { y: 3, x: 4 }, true);
+        // 使っていないマスは通らない
+        grid.set(&Pos { y: 0, x: 2 }, false);
+
+        let (path, weight) = grid
+            .find_current_path_to_source(&Pos { y: 4, x: 4 })
+            .unwrap();
+        assert_eq!(path.first(), Some(&Pos { y: 4, x: 4 }));
+        assert_eq!(path.last(), Some(&Pos { y: 0, x: 0 }));
+        // (4, 4) -> (2, 4) -> (2, 0) -> (0, 0)
+        assert_eq!(path.len(), 9);
+        assert_eq!(weight, 9);
+        assert!(path.windows(2).all(|w| w[0].manhattan_dist(&w[1]) == 1));
+        assert!(path.iter().all(|p| grid.is_used.get(p)));
+
+        // 使っているマスで水源まで辿れない家
+        assert!(grid
+            .find_current_path_to_source(&Pos { y: 4, x: 0 })
+            .is_none());
+    }
+
+    #[test]
+    fn path_to_water_attaches_to_watered_branch() {
+        let (n, m) = (5, 6);
+        let source = vec![Pos { y: 0, x: 0 }];
+        let house = vec![Pos { y: 4, x: 5 }, Pos { y: 4, x: 0 }];
+        let mut grid = Grid::new(Vec2d::new(n, m, 1), Vec2d::new(n, m, false), house, source);
+        // 水源から右端まで伸びる枝と、水に繋がっていない枝
+        for x in 0..m as i64 {
+            grid.set(&Pos { y: 0, x }, true);
+        }
+        grid.set(&Pos { y: 3, x: 0 }, true);
+
+        let (path, attach) = grid.find_path_to_water(&Pos { y: 4, x: 5 }, INF).unwrap();
+        assert_eq!(attach, Pos { y: 0, x: 5 });
+        assert_eq!(path.first(), Some(&Pos { y: 4, x: 5 }));
+        assert_eq!(path.last(), Some(&attach));
+        assert_eq!(path.len(), 5);
+
+        // 水に繋がっていない枝は通るだけで、合流先にはならない
+        let (_, attach) = grid.find_path_to_water(&Pos { y: 4, x: 0 }, INF).unwrap();
+        assert_eq!(attach.y, 0);
+        assert!(grid.find_path_to_water(&Pos { y: 4, x: 0 }, 1).is_none());
+    }
+
+    #[test]
+    fn connectivity_is_kept_after_removals() {
+        let (n, m) = (6, 7);
+        let source = vec![Pos { y: 0, x: 0 }, Pos { y: 5, x: 6 }];
+        let house = vec![Pos { y: 0, x: 6 }, Pos { y: 3, x: 3 }, Pos { y: 5, x: 0 }];
+        let mut grid = Grid::new(Vec2d::new(n, m, 1), Vec2d::new(n, m, false), house, source);
+
+        let mut s: u64 = 12345;
+        for _ in 0..2000 {
+            s ^= s << 13;
+            s ^= s >> 7;
+            s ^= s << 17;
+            let p = Pos {
+                y: (s % n as u64) as i64,
+                x: (s / n as u64 % m as u64) as i64,
+            };
+            // 外す方を少なくして、繋がっている状態も十分に現れるようにする
+            let v = !(s / (n * m) as u64).is_multiple_of(3);
+            grid.set(&p, v);
+
+            assert_eq!(
+                grid.find_unconnected_houses(),
+                unconnected_houses_by_bfs(&grid)
+            );
+            let used = grid.is_used.iter().filter(|(_, &is_used)| is_used).count();
+            assert_eq!(grid.used_cells.len(), used);
+            assert_eq!(grid.total_score, used as i64);
+        }
+    }
+}
@@ -1,22 +1,89 @@
 use proconio::*;
 
-use crate::{def::*, util::time};
-use std::io::{Stdin, Write};
+use crate::{clock::Clock, def::*, judge::Judge};
+use std::{
+    collections::VecDeque,
+    io::{Stdin, Write},
+};
 
-pub struct Interactor {
+/// 掘削に対するジャッジの応答
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Response {
+    NotBroken,
+    Broken,
+    /// 全ての家に水が流れ、処理を終了する
+    AllConnected,
+    /// 不正な掘削で、処理を終了する
+    Invalid,
+}
+
+impl Response {
+    /// ジャッジが返す値に戻す
+    pub fn code(&self) -> i64 {
+        match self {
+            Response::NotBroken => 0,
+            Response::Broken => 1,
+            Response::AllConnected => 2,
+            Response::Invalid => -1,
+        }
+    }
+}
+
+pub trait Interactor {
+    fn read_input(&mut self) -> Input;
+
+    /// solverが時間で処理を打ち切る時に読む時計
+    fn clock(&mut self) -> Clock {
+        Clock::wall()
+    }
+
+    /// `y x P`を送り、ジャッジの応答を返す
+    fn respond(&mut self, pos: &Pos, power: i64) -> i64;
+
+    fn add_damage(&mut self, pos: &Pos, power: i64, state: &mut State) -> Response {
+        state.add_damage(pos, power);
+
+        let r = self.respond(pos, power);
+
+        match r {
+            0 => {
+                state.damage_before_break.set(pos, state.damage.get(pos));
+                Response::NotBroken
+            }
+            1 => {
+                state.is_broken.set(pos, true);
+                Response::Broken
+            }
+            2 => {
+                state.is_broken.set(pos, true);
+                Response::AllConnected
+            }
+            -1 => Response::Invalid,
+            _ => {
+                eprintln!("Invalid result output: {}", r);
+                Response::Invalid
+            }
+        }
+    }
+}
+
+/// 標準入出力で公式ジャッジとやり取りする
+pub struct StdinInteractor {
     source: proconio::source::line::LineSource<std::io::BufReader<Stdin>>,
 }
 
-impl Interactor {
-    pub fn new() -> Interactor {
-        Interactor {
+impl StdinInteractor {
+    pub fn new() -> StdinInteractor {
+        StdinInteractor {
             source: proconio::source::line::LineSource::new(std::io::BufReader::new(
                 std::io::stdin(),
             )),
         }
     }
+}
 
-    pub fn read_input(&mut self) -> Input {
+impl Interactor for StdinInteractor {
+    fn read_input(&mut self) -> Input {
         input! {
             from &mut self.source,
             n: usize,
@@ -44,6 +111,7 @@ impl Interactor {
         }
         Input {
             n,
+            m: n,
             w,
             k,
             c,
@@ -52,32 +120,219 @@ impl Interactor {
         }
     }
 
-    pub fn add_damage(&mut self, pos: &Pos, power: i64, state: &mut State) -> bool {
+    fn respond(&mut self, pos: &Pos, power: i64) -> i64 {
         println!("{} {} {}", pos.y, pos.x, power);
         std::io::stdout().flush().unwrap();
 
-        state.total_damage += power;
-        state.damage.set(pos, state.damage.get(pos) + power);
-
         input! {
             from &mut self.source,
             r: i64,
         }
+        r
+    }
+}
+
+/// 頑丈度を与えて、プロセス内のオフラインジャッジとやり取りする
+pub struct InMemoryInteractor {
+    judge: Judge,
+}
 
-        if r == 0 {
-            state.damage_before_break.set(pos, state.damage.get(pos));
-            return false;
-        } else if r == 1 {
-            state.is_broken.set(pos, true);
-            return true;
-        } else if r == 2 {
-            // 終了する
-            eprintln!("elapsed seconds: {:.4}", time::elapsed_seconds());
-            std::process::exit(0);
-        } else if r == -1 {
-            panic!("Invalid operation");
-        } else {
-            panic!("Invalid result output");
+#[allow(unused)]
+impl InMemoryInteractor {
+    pub fn new(input: Input, hardness: Vec2d<i64>) -> InMemoryInteractor {
+        InMemoryInteractor::from_judge(Judge::new(input, hardness))
+    }
+
+    pub fn from_judge(judge: Judge) -> InMemoryInteractor {
+        InMemoryInteractor { judge }
+    }
+
+    pub fn total_cost(&self) -> i64 {
+        self.judge.total_cost()
+    }
+}
+
+impl Interactor for InMemoryInteractor {
+    fn read_input(&mut self) -> Input {
+        self.judge.input()
+    }
+
+    fn respond(&mut self, pos: &Pos, power: i64) -> i64 {
+        let r = self.judge.query(pos, power);
+        if r == 2 {
+            eprintln!("Total Cost = {}", self.judge.total_cost());
         }
+        r
+    }
+}
+
+/// 記録したやり取りを再生し、同じ掘削が行われていることを確かめる
+pub struct ReplayInteractor {
+    input: Input,
+    records: VecDeque<(Pos, i64, i64)>,
+    /// 記録した時に、solverが時計から読んだ時刻
+    clock_readings: Vec<f64>,
+    query_count: usize,
+}
+
+impl ReplayInteractor {
+    /// 入力（N W K C M、水源、家）に続けて、`y x P r`が1行ずつ並んだ記録を読み込む
+    /// Mのない記録も読み込める
+    /// 各行の5列目以降は無視する
+    /// `clock t`の行は、solverが読んだ時刻として順に再生する
+    pub fn from_transcript(text: &str) -> ReplayInteractor {
+        let mut lines = text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let mut next_values = || -> Vec<i64> {
+            lines
+                .next()
+                .expect("Unexpected end of transcript")
+                .split_whitespace()
+                .map_while(|v| v.parse::<i64>().ok())
+                .collect()
+        };
+
+        let header = next_values();
+        let (n, w, k, c) = (
+            header[0] as usize,
+            header[1] as usize,
+            header[2] as usize,
+            header[3],
+        );
+        // 列数のない記録は正方形の盤面とみなす
+        let m = header.get(4).map_or(n, |&m| m as usize);
+        let mut read_pos = || {
+            let v = next_values();
+            Pos { y: v[0], x: v[1] }
+        };
+        let source = (0..w).map(|_| read_pos()).collect();
+        let house = (0..k).map(|_| read_pos()).collect();
+
+        let mut records = VecDeque::new();
+        let mut clock_readings = vec![];
+        for line in lines {
+            if let Some(t) = line.strip_prefix("clock ") {
+                clock_readings.push(t.trim().parse::<f64>().unwrap());
+                continue;
+            }
+            let v: Vec<i64> = line
+                .split_whitespace()
+                .take(4)
+                .map(|v| v.parse::<i64>().unwrap())
+                .collect();
+            records.push_back((Pos { y: v[0], x: v[1] }, v[2], v[3]));
+        }
+
+        ReplayInteractor {
+            input: Input {
+                n,
+                m,
+                w,
+                k,
+                c,
+                source,
+                house,
+            },
+            records,
+            clock_readings,
+            query_count: 0,
+        }
+    }
+}
+
+impl Interactor for ReplayInteractor {
+    fn read_input(&mut self) -> Input {
+        self.input.clone()
+    }
+
+    /// 記録した時と同じところで時間切れになるように、記録した時刻を返す
+    fn clock(&mut self) -> Clock {
+        Clock::replay(std::mem::take(&mut self.clock_readings))
+    }
+
+    fn respond(&mut self, pos: &Pos, power: i64) -> i64 {
+        self.query_count += 1;
+        // 記録と異なる掘削をした場合は、不正な掘削として扱う
+        let Some((expected_pos, expected_power, r)) = self.records.pop_front() else {
+            eprintln!(
+                "Replay exhausted at query {}: {} {} {}",
+                self.query_count, pos.y, pos.x, power
+            );
+            return -1;
+        };
+        if &expected_pos != pos || expected_power != power {
+            eprintln!(
+                "Replay diverged at query {}: expected {} {} {}, got {} {} {}",
+                self.query_count,
+                expected_pos.y,
+                expected_pos.x,
+                expected_power,
+                pos.y,
+                pos.x,
+                power
+            );
+            return -1;
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSCRIPT: &str = "# comment
+2 1 1 8 3
+0 0
+1 2
+0 1 100 0 0.0100 survey
+0 1 50 1 0.0200 survey
+";
+
+    #[test]
+    fn replay_returns_recorded_responses() {
+        let mut replay = ReplayInteractor::from_transcript(TRANSCRIPT);
+        let input = replay.read_input();
+        assert_eq!(
+            (input.n, input.m, input.w, input.k, input.c),
+            (2, 3, 1, 1, 8)
+        );
+        assert_eq!(input.source, vec![Pos { y: 0, x: 0 }]);
+        assert_eq!(input.house, vec![Pos { y: 1, x: 2 }]);
+
+        assert_eq!(replay.respond(&Pos { y: 0, x: 1 }, 100), 0);
+        assert_eq!(replay.respond(&Pos { y: 0, x: 1 }, 50), 1);
+        // 記録を使い切った後は不正な掘削として扱う
+        assert_eq!(replay.respond(&Pos { y: 0, x: 1 }, 50), -1);
+    }
+
+    #[test]
+    fn replay_rejects_diverged_queries() {
+        let mut replay = ReplayInteractor::from_transcript(TRANSCRIPT);
+        assert_eq!(replay.respond(&Pos { y: 0, x: 1 }, 99), -1);
+
+        let mut replay = ReplayInteractor::from_transcript(TRANSCRIPT);
+        assert_eq!(replay.respond(&Pos { y: 1, x: 1 }, 100), -1);
+    }
+
+    #[test]
+    fn replay_returns_recorded_clock_readings() {
+        let transcript = format!("{}clock 0.5\nclock 4.25\n0 2 10 1\n", TRANSCRIPT);
+        let mut replay = ReplayInteractor::from_transcript(&transcript);
+        let clock = replay.clock();
+        assert_eq!(clock.elapsed_seconds(), 0.5);
+        assert_eq!(clock.elapsed_seconds(), 4.25);
+        assert_eq!(replay.respond(&Pos { y: 0, x: 1 }, 100), 0);
+        assert_eq!(replay.respond(&Pos { y: 0, x: 1 }, 50), 1);
+        assert_eq!(replay.respond(&Pos { y: 0, x: 2 }, 10), 1);
+    }
+
+    #[test]
+    fn replay_without_column_count_is_square() {
+        let mut replay = ReplayInteractor::from_transcript("2 1 1 8\n0 0\n1 1\n");
+        let input = replay.read_input();
+        assert_eq!((input.n, input.m), (2, 2));
     }
 }
@@ -0,0 +1,305 @@
+use crate::{def::*, util::UnionFind};
+
+/// 公式の盤面の一辺
+const DEFAULT_SIZE: usize = 200;
+
+/// 公式の盤面で、水源と家を互いに離すマンハッタン距離
+const DEFAULT_SEPARATION: usize = 10;
+
+/// 水源と家を置く位置を引き直す回数の上限
+const MAX_PLACEMENT_TRIALS: usize = 100000;
+
+/// ジャッジ専用の乱数（solver側の`util::rnd`とは独立させる）
+struct XorShift {
+    s: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // splitmix64でseedを拡散する
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        XorShift { s: z | 1 }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.s ^= self.s << 7;
+        self.s ^= self.s >> 9;
+        self.s
+    }
+
+    fn nextf(&mut self) -> f64 {
+        (self.next() & 4294967295) as f64 / 4294967296.
+    }
+
+    fn gen_range(&mut self, low: u64, high: u64) -> u64 {
+        self.next() % (high - low) + low
+    }
+}
+
+/// 公式ジャッジと同じ応答を返すオフラインジャッジ
+pub struct Judge {
+    input: Input,
+    hardness: Vec2d<i64>,
+    damage: Vec2d<i64>,
+    is_broken: Vec2d<bool>,
+    uf: UnionFind,
+    total_cost: i64,
+    is_finished: bool,
+}
+
+#[allow(unused)]
+impl Judge {
+    pub fn new(input: Input, hardness: Vec2d<i64>) -> Judge {
+        let (n, m) = (input.n, input.m);
+        Judge {
+            input,
+            hardness,
+            damage: Vec2d::new(n, m, 0),
+            is_broken: Vec2d::new(n, m, false),
+            // n * mは水源をまとめた仮想的な頂点
+            uf: UnionFind::new(n * m + 1),
+            total_cost: 0,
+            is_finished: false,
+        }
+    }
+
+    /// seedから、公式と同じ大きさの盤面を生成する
+    pub fn generate(seed: u64) -> Judge {
+        Judge::generate_with_size(seed, DEFAULT_SIZE, DEFAULT_SIZE)
+    }
+
+    /// seedから、n行m列の盤面の頑丈度と家・水源の配置を生成する
+    pub fn generate_with_size(seed: u64, n: usize, m: usize) -> Judge {
+        let mut rng = XorShift::new(seed);
+        let w = rng.gen_range(1, 5) as usize;
+        let k = rng.gen_range(1, 11) as usize;
+        let c = 1 << rng.gen_range(0, 8);
+
+        // 水源と家は互いに離す（小さい盤面では盤面の大きさに合わせて距離を縮める）
+        let separation = usize::max(1, DEFAULT_SEPARATION * n.min(m) / DEFAULT_SIZE) as i64;
+        let mut ps: Vec<Pos> = vec![];
+        let mut trials = 0;
+        while ps.len() < w + k {
+            trials += 1;
+            assert!(
+                trials <= MAX_PLACEMENT_TRIALS,
+                "Cannot place {} sources and houses on a {}x{} board",
+                w + k,
+                n,
+                m
+            );
+            let p = Pos {
+                y: rng.gen_range(0, n as u64) as i64,
+                x: rng.gen_range(0, m as u64) as i64,
+            };
+            if ps.iter().all(|q| q.manhattan_dist(&p) >= separation) {
+                ps.push(p);
+            }
+        }
+        let house = ps.split_off(w);
+        let source = ps;
+
+        let hardness = generate_hardness(n, m, &mut rng);
+        let input = Input {
+            n,
+            m,
+            w,
+            k,
+            c,
+            source,
+            house,
+        };
+        Judge::new(input, hardness)
+    }
+
+    /// 公式ツールの入力ファイル（N W K C、頑丈度、水源、家）を読み込む
+    pub fn from_input_text(text: &str) -> Judge {
+        let mut it = text.split_whitespace().map(|v| v.parse::<i64>().unwrap());
+        let mut next = || it.next().expect("Unexpected end of input file");
+        let (n, w, k, c) = (next() as usize, next() as usize, next() as usize, next());
+
+        let mut hardness = Vec2d::new(n, n, 0);
+        for y in 0..n as i64 {
+            for x in 0..n as i64 {
+                hardness.set(&Pos { y, x }, next());
+            }
+        }
+        let source = (0..w)
+            .map(|_| Pos {
+                y: next(),
+                x: next(),
+            })
+            .collect();
+        let house = (0..k)
+            .map(|_| Pos {
+                y: next(),
+                x: next(),
+            })
+            .collect();
+        let input = Input {
+            n,
+            m: n,
+            w,
+            k,
+            c,
+            source,
+            house,
+        };
+        Judge::new(input, hardness)
+    }
+
+    pub fn input(&self) -> Input {
+        self.input.clone()
+    }
+
+    pub fn hardness(&self) -> &Vec2d<i64> {
+        &self.hardness
+    }
+
+    pub fn total_cost(&self) -> i64 {
+        self.total_cost
+    }
+
+    /// `y x P`の掘削に対して0/1/2/-1を返す
+    pub fn query(&mut self, pos: &Pos, power: i64) -> i64 {
+        let (n, m) = (self.input.n, self.input.m);
+        if self.is_finished || !pos.is_valid(n, m) || !(1..=S_MAX).contains(&power) {
+            return -1;
+        }
+        if self.is_broken.get(pos) {
+            return -1;
+        }
+
+        self.total_cost += power + self.input.c;
+        self.damage.set(pos, self.damage.get(pos) + power);
+        if self.damage.get(pos) < self.hardness.get(pos) {
+            return 0;
+        }
+
+        self.is_broken.set(pos, true);
+        for np in self.is_broken.neighbors(pos) {
+            if self.is_broken.get(&np) {
+                self.uf.unite(pos.to_idx(m), np.to_idx(m));
+            }
+        }
+        let root = n * m;
+        if self.input.source.contains(pos) {
+            self.uf.unite(pos.to_idx(m), root);
+        }
+
+        let is_all_connected = self
+            .input
+            .house
+            .iter()
+            .all(|h| self.uf.same(h.to_idx(m), root));
+        if is_all_connected {
+            self.is_finished = true;
+            return 2;
+        }
+        1
+    }
+}
+
+/// 複数オクターブのノイズを重ね合わせて、なめらかな頑丈度を生成する
+fn generate_hardness(n: usize, m: usize, rng: &mut XorShift) -> Vec2d<i64> {
+    const OCTAVES: [(f64, f64); 3] = [(50., 1.), (25., 0.5), (12.5, 0.25)];
+
+    let mut noise = vec![0.; n * m];
+    for (period, amp) in OCTAVES {
+        let size = (usize::max(n, m) as f64 / period).ceil() as usize + 2;
+        let lattice: Vec<f64> = (0..size * size).map(|_| rng.nextf()).collect();
+        let (oy, ox) = (rng.nextf(), rng.nextf());
+        for y in 0..n {
+            for x in 0..m {
+                let fy = y as f64 / period + oy;
+                let fx = x as f64 / period + ox;
+                let (iy, ix) = (fy as usize, fx as usize);
+                let (ty, tx) = (smoothstep(fy - iy as f64), smoothstep(fx - ix as f64));
+                let v00 = lattice[iy * size + ix];
+                let v01 = lattice[iy * size + ix + 1];
+                let v10 = lattice[(iy + 1) * size + ix];
+                let v11 = lattice[(iy + 1) * size + ix + 1];
+                let v0 = v00 + (v01 - v00) * tx;
+                let v1 = v10 + (v11 - v10) * tx;
+                noise[y * m + x] += (v0 + (v1 - v0) * ty) * amp;
+            }
+        }
+    }
+
+    let lo = noise.iter().cloned().fold(f64::MAX, f64::min);
+    let hi = noise.iter().cloned().fold(f64::MIN, f64::max);
+    let mut hardness = Vec2d::new(n, m, S_MIN);
+    for y in 0..n {
+        for x in 0..m {
+            // 低い頑丈度のマスが多くなるように偏らせる
+            let u = ((noise[y * m + x] - lo) / (hi - lo)).powf(1.5);
+            let s = S_MIN as f64 * ((S_MAX / S_MIN) as f64).powf(u);
+            let p = Pos {
+                y: y as i64,
+                x: x as i64,
+            };
+            hardness.set(&p, (s.round() as i64).clamp(S_MIN, S_MAX));
+        }
+    }
+    hardness
+}
+
+fn smoothstep(t: f64) -> f64 {
+    t * t * (3. - 2. * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1行3列で、左端が水源、右端が家の盤面
+    fn line_judge() -> Judge {
+        let input = Input {
+            n: 1,
+            m: 3,
+            w: 1,
+            k: 1,
+            c: 4,
+            source: vec![Pos { y: 0, x: 0 }],
+            house: vec![Pos { y: 0, x: 2 }],
+        };
+        let mut hardness = Vec2d::new(1, 3, 10);
+        hardness.set(&Pos { y: 0, x: 1 }, 30);
+        Judge::new(input, hardness)
+    }
+
+    #[test]
+    fn query_responds_like_the_official_judge() {
+        let mut judge = line_judge();
+        let (source, middle, house) = (Pos { y: 0, x: 0 }, Pos { y: 0, x: 1 }, Pos { y: 0, x: 2 });
+
+        // 不正な掘削はコストもかからない
+        assert_eq!(judge.query(&Pos { y: 1, x: 0 }, 10), -1);
+        assert_eq!(judge.query(&Pos { y: 0, x: -1 }, 10), -1);
+        assert_eq!(judge.query(&middle, 0), -1);
+        assert_eq!(judge.query(&middle, S_MAX + 1), -1);
+        assert_eq!(judge.total_cost(), 0);
+
+        assert_eq!(judge.query(&middle, 20), 0);
+        assert_eq!(judge.query(&middle, 10), 1);
+        // 割れたマスは叩けない
+        assert_eq!(judge.query(&middle, 10), -1);
+        assert_eq!(judge.query(&source, 10), 1);
+        assert_eq!(judge.query(&house, S_MAX), 2);
+        assert_eq!(judge.total_cost(), 20 + 10 + 10 + S_MAX + 4 * 4);
+
+        // 全て繋がった後は終了している
+        assert_eq!(judge.query(&Pos { y: 0, x: 1 }, 10), -1);
+    }
+
+    #[test]
+    fn house_is_not_connected_without_source() {
+        let mut judge = line_judge();
+        assert_eq!(judge.query(&Pos { y: 0, x: 2 }, 10), 1);
+        assert_eq!(judge.query(&Pos { y: 0, x: 1 }, 30), 1);
+        assert_eq!(judge.query(&Pos { y: 0, x: 0 }, 10), 2);
+    }
+}
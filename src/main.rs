@@ -1,7 +1,20 @@
+mod beam;
+mod clock;
+mod cost_model;
 mod def;
+mod dijkstra;
+mod estimator;
 mod grid;
 mod interactor;
+mod judge;
+mod params;
+mod planner;
+mod policy;
+mod region;
 mod solver;
+mod steiner;
+mod strike;
+mod transcript;
 mod util;
 
 use util::*;
@@ -9,6 +22,51 @@ use util::*;
 fn main() {
     time::start_clock();
 
-    let mut solver = solver::Solver::new();
-    solver.solve();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let transcript_path = take_option(&mut args, "--transcript");
+    let size = take_option(&mut args, "--size");
+
+    let interactor = create_interactor(&args, size.as_deref());
+    let mut solver = solver::Solver::new(interactor, params::Params::load());
+    if let Some(path) = transcript_path {
+        solver.record_transcript(&path);
+    }
+    if solver.solve() != interactor::Response::AllConnected {
+        std::process::exit(1);
+    }
+}
+
+/// ローカルでは引数にseedか入力ファイルを渡すと、オフラインジャッジで実行する
+/// `--replay <file>`を渡すと、記録したやり取りを再生する
+/// seedと一緒に`--size <n>`か`--size <n>x<m>`を渡すと、その大きさの盤面を生成する
+fn create_interactor(args: &[String], size: Option<&str>) -> Box<dyn interactor::Interactor> {
+    if !cfg!(feature = "local") || args.is_empty() {
+        return Box::new(interactor::StdinInteractor::new());
+    }
+    if args[0] == "--replay" {
+        let transcript = std::fs::read_to_string(&args[1]).unwrap();
+        return Box::new(interactor::ReplayInteractor::from_transcript(&transcript));
+    }
+    let judge = match args[0].parse::<u64>() {
+        Ok(seed) => match size {
+            Some(size) => {
+                let (n, m) = size.split_once('x').unwrap_or((size, size));
+                judge::Judge::generate_with_size(seed, n.parse().unwrap(), m.parse().unwrap())
+            }
+            None => judge::Judge::generate(seed),
+        },
+        Err(_) => judge::Judge::from_input_text(&std::fs::read_to_string(&args[0]).unwrap()),
+    };
+    Box::new(interactor::InMemoryInteractor::from_judge(judge))
+}
+
+/// ローカルでのみ`name <value>`形式の引数を取り出す
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    if !cfg!(feature = "local") {
+        return None;
+    }
+    let i = args.iter().position(|arg| arg == name)?;
+    let value = args.get(i + 1).cloned();
+    args.drain(i..usize::min(i + 2, args.len()));
+    value
 }
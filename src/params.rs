@@ -0,0 +1,252 @@
+/// 公式の盤面の面積
+const OFFICIAL_AREA: usize = 200 * 200;
+
+/// solverの調整用の定数
+///
+/// 提出時は`Default`の値を使う
+/// ローカルでは、`AHC018_PARAMS`で指定したファイルの`name = value`と、
+/// `AHC018_<NAME>`の環境変数で上書きできる
+/// 時刻と時間は公式の盤面でのもので、`scale_time_limits`で盤面の大きさに合わせる
+#[derive(Debug, Clone)]
+pub struct Params {
+    /// 経路を割る時に選ぶ累積のパワーを、何倍ずつの区切りにするか
+    pub strike_level_ratio: f64,
+    /// 選択経路の周りの調査を打ち切る時刻（秒）
+    pub refine_time_limit: f64,
+    /// 経路の最適化を打ち切る時刻（秒）
+    pub optimize_time_limit: f64,
+    /// 割る途中で経路を選び直すのは、この時刻（秒）から残りのマスを割る時間を引いた時刻まで
+    pub reroute_time_limit: f64,
+    /// 経路を選び直す時に、最適化にかける時間（秒）
+    pub reroute_time: f64,
+    /// 経路を選び直せる時刻を決める時に、残りの1マスを割るために残しておく時間（秒）
+    pub excavation_time_per_cell: f64,
+    /// 経路を選び直すのは、前回選んでからのコストが予測の何倍を超えた時か
+    pub reroute_ratio: f64,
+    /// 経路を選び直すのは、前回選んでからのコストが予測をいくつ超えた時か
+    pub reroute_min_excess: f64,
+    /// 経路の焼きなましの開始温度
+    pub anneal_start_temp: f64,
+    /// 経路の焼きなましの終了温度
+    pub anneal_end_temp: f64,
+    /// 家の数がこれ以下なら、Dreyfus-Wagnerで厳密なシュタイナー木を求める
+    pub dreyfus_wagner_max_houses: usize,
+    /// 経路の初期解を、家をランダムな順に繋いで作り直す回数
+    pub route_random_restarts: usize,
+    /// シュタイナー木の鍵パスを交換する周回の上限
+    pub key_path_exchange_passes: usize,
+    /// 最後の経路をビームサーチで作る時の幅（0なら初期解を焼きなましで最適化する）
+    pub route_beam_width: usize,
+    /// 焼きなましで経路を消す正方形の、半径の最小値
+    pub rip_up_radius_min: usize,
+    /// 焼きなましで経路を消す正方形の、半径の最大値
+    pub rip_up_radius_max: usize,
+
+    /// 観測がない時の頑丈度
+    pub default_hardness: f64,
+    /// 頑丈度の予測に使う観測を探す範囲
+    pub estimate_radius: f64,
+    /// ガウス過程のカーネルの長さスケール
+    pub length_scale: f64,
+    /// ガウス過程のカーネルの分散（log2）
+    pub signal_var: f64,
+    /// 全ての観測に加えるノイズの分散（log2）
+    pub nugget: f64,
+    /// 同じ観測で同時に予測するブロックの一辺
+    pub estimate_block: usize,
+    /// 予測に使う観測の最大数
+    pub estimate_neighbors: usize,
+    /// 下限付きの観測の補完に使う観測の最大数
+    pub impute_neighbors: usize,
+
+    /// C = 1の時に最初に調査する格子の間隔
+    pub survey_spacing: f64,
+    /// C = 1の時に調査で最初に与えるパワー
+    pub probe_first_power: f64,
+    /// C = 1の時に調査でパワーを何倍ずつ増やすか
+    pub probe_ladder_ratio: f64,
+    /// C = 1の時に最初の調査で与えるパワーの上限
+    pub survey_top_power: i64,
+    /// C = 1の時に選択経路の周りの調査で与えるパワーの上限
+    pub refine_top_power: i64,
+    /// 1回の調査に使うパワーの、選択経路の推定コストに対する割合
+    pub probe_budget_ratio: f64,
+    /// 調査で得られる観測のノイズの分散（log2）
+    pub probe_noise: f64,
+    /// 調査の価値を見積もる時に、経路の近くのマスを迂回路の候補とする範囲
+    pub alternative_radius: i64,
+    /// 迂回路の候補となるマスの不確かさの重み
+    pub alternative_weight: f64,
+
+    /// log2(C)が1増えるごとに、調査の格子の間隔をsurvey_spacingの何倍ずつ広げるか
+    pub survey_spacing_c_slope: f64,
+    /// log2(C)が1増えるごとに、調査で最初に与えるパワーをprobe_first_powerの何倍ずつ増やすか
+    pub probe_first_power_c_slope: f64,
+    /// log2(C)が1増えるごとに、調査でパワーを増やす倍率をいくつ大きくするか
+    pub probe_ladder_c_slope: f64,
+    /// log2(C)が1増えるごとに、最初の調査で与えるパワーの上限をsurvey_top_powerの何倍ずつ増やすか
+    pub survey_top_power_c_slope: f64,
+    /// log2(C)が1増えるごとに、選択経路の周りの調査で与えるパワーの上限をrefine_top_powerの何倍ずつ増やすか
+    pub refine_top_power_c_slope: f64,
+    /// 選択経路の周りを調査する回数は、
+    /// refine_passes_base + K * refine_passes_per_house - (W - 1) * refine_passes_per_extra_source - log2(C) * refine_passes_c_slope
+    pub refine_passes_base: f64,
+    pub refine_passes_per_house: f64,
+    pub refine_passes_per_extra_source: f64,
+    pub refine_passes_c_slope: f64,
+    /// 選択経路の周りを調査する回数の最小値
+    pub refine_passes_min: usize,
+    /// 選択経路の周りを調査する回数の最大値
+    pub refine_passes_max: usize,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            strike_level_ratio: 1.08,
+            refine_time_limit: 2.5,
+            optimize_time_limit: 4.,
+            reroute_time_limit: 4.6,
+            reroute_time: 0.1,
+            excavation_time_per_cell: 0.0003,
+            reroute_ratio: 1.1,
+            reroute_min_excess: 1000.,
+            anneal_start_temp: 500.,
+            anneal_end_temp: 10.,
+            dreyfus_wagner_max_houses: 4,
+            route_random_restarts: 2,
+            key_path_exchange_passes: 3,
+            route_beam_width: 0,
+            rip_up_radius_min: 2,
+            rip_up_radius_max: 11,
+
+            default_hardness: 10.,
+            estimate_radius: 30.,
+            length_scale: 12.,
+            signal_var: 2.25,
+            nugget: 0.05,
+            estimate_block: 8,
+            estimate_neighbors: 48,
+            impute_neighbors: 32,
+
+            survey_spacing: 20.,
+            probe_first_power: 13.,
+            probe_ladder_ratio: 4.,
+            survey_top_power: 100,
+            refine_top_power: 500,
+            probe_budget_ratio: 0.1,
+            probe_noise: 0.3,
+            alternative_radius: 6,
+            alternative_weight: 0.3,
+
+            survey_spacing_c_slope: 1. / 14.,
+            probe_first_power_c_slope: 0.5,
+            probe_ladder_c_slope: 0.5,
+            survey_top_power_c_slope: 0.15,
+            refine_top_power_c_slope: 0.14,
+            refine_passes_base: 3.,
+            refine_passes_per_house: 0.5,
+            refine_passes_per_extra_source: 0.5,
+            refine_passes_c_slope: 1. / 3.,
+            refine_passes_min: 2,
+            refine_passes_max: 7,
+        }
+    }
+}
+
+impl Params {
+    pub fn load() -> Params {
+        let mut params = Params::default();
+        if !cfg!(feature = "local") {
+            return params;
+        }
+
+        if let Ok(path) = std::env::var("AHC018_PARAMS") {
+            let text = std::fs::read_to_string(&path).unwrap();
+            for line in text.lines() {
+                let line = line.split('#').next().unwrap().trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let (name, value) = line.split_once('=').expect("Expected `name = value`");
+                params.set(name.trim(), value.trim());
+            }
+        }
+        for (key, value) in std::env::vars() {
+            if let Some(name) = key.strip_prefix("AHC018_") {
+                if name != "PARAMS" {
+                    params.set(&name.to_lowercase(), &value);
+                }
+            }
+        }
+        params
+    }
+
+    /// n行m列の盤面に合わせて、時刻と時間を面積に比例して短くする
+    /// 処理にかかる時間は盤面の面積にほぼ比例するので、小さい盤面では短い時間で十分に最適化できる
+    pub fn scale_time_limits(&mut self, n: usize, m: usize) {
+        let ratio = f64::min(1., (n * m) as f64 / OFFICIAL_AREA as f64);
+        self.refine_time_limit *= ratio;
+        self.optimize_time_limit *= ratio;
+        self.reroute_time_limit *= ratio;
+        self.reroute_time *= ratio;
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        fn parse<T: std::str::FromStr>(name: &str, value: &str) -> T {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid value for {}: {}", name, value))
+        }
+
+        match name {
+            "strike_level_ratio" => self.strike_level_ratio = parse(name, value),
+            "refine_time_limit" => self.refine_time_limit = parse(name, value),
+            "optimize_time_limit" => self.optimize_time_limit = parse(name, value),
+            "reroute_time_limit" => self.reroute_time_limit = parse(name, value),
+            "reroute_time" => self.reroute_time = parse(name, value),
+            "excavation_time_per_cell" => self.excavation_time_per_cell = parse(name, value),
+            "reroute_ratio" => self.reroute_ratio = parse(name, value),
+            "reroute_min_excess" => self.reroute_min_excess = parse(name, value),
+            "anneal_start_temp" => self.anneal_start_temp = parse(name, value),
+            "anneal_end_temp" => self.anneal_end_temp = parse(name, value),
+            "dreyfus_wagner_max_houses" => self.dreyfus_wagner_max_houses = parse(name, value),
+            "route_random_restarts" => self.route_random_restarts = parse(name, value),
+            "key_path_exchange_passes" => self.key_path_exchange_passes = parse(name, value),
+            "route_beam_width" => self.route_beam_width = parse(name, value),
+            "rip_up_radius_min" => self.rip_up_radius_min = parse(name, value),
+            "rip_up_radius_max" => self.rip_up_radius_max = parse(name, value),
+            "default_hardness" => self.default_hardness = parse(name, value),
+            "estimate_radius" => self.estimate_radius = parse(name, value),
+            "length_scale" => self.length_scale = parse(name, value),
+            "signal_var" => self.signal_var = parse(name, value),
+            "nugget" => self.nugget = parse(name, value),
+            "estimate_block" => self.estimate_block = parse(name, value),
+            "estimate_neighbors" => self.estimate_neighbors = parse(name, value),
+            "impute_neighbors" => self.impute_neighbors = parse(name, value),
+            "survey_spacing" => self.survey_spacing = parse(name, value),
+            "probe_first_power" => self.probe_first_power = parse(name, value),
+            "probe_ladder_ratio" => self.probe_ladder_ratio = parse(name, value),
+            "survey_top_power" => self.survey_top_power = parse(name, value),
+            "refine_top_power" => self.refine_top_power = parse(name, value),
+            "probe_budget_ratio" => self.probe_budget_ratio = parse(name, value),
+            "probe_noise" => self.probe_noise = parse(name, value),
+            "alternative_radius" => self.alternative_radius = parse(name, value),
+            "alternative_weight" => self.alternative_weight = parse(name, value),
+            "survey_spacing_c_slope" => self.survey_spacing_c_slope = parse(name, value),
+            "probe_first_power_c_slope" => self.probe_first_power_c_slope = parse(name, value),
+            "probe_ladder_c_slope" => self.probe_ladder_c_slope = parse(name, value),
+            "survey_top_power_c_slope" => self.survey_top_power_c_slope = parse(name, value),
+            "refine_top_power_c_slope" => self.refine_top_power_c_slope = parse(name, value),
+            "refine_passes_base" => self.refine_passes_base = parse(name, value),
+            "refine_passes_per_house" => self.refine_passes_per_house = parse(name, value),
+            "refine_passes_per_extra_source" => {
+                self.refine_passes_per_extra_source = parse(name, value)
+            }
+            "refine_passes_c_slope" => self.refine_passes_c_slope = parse(name, value),
+            "refine_passes_min" => self.refine_passes_min = parse(name, value),
+            "refine_passes_max" => self.refine_passes_max = parse(name, value),
+            _ => panic!("Unknown parameter: {}", name),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{
+    cost_model::CostModel,
+    def::*,
+    estimator::{normal_cdf, Estimate, Estimator, HardnessField},
+    grid::Grid,
+    params::Params,
+};
+
+/// 不確かさを減らしたいマス
+struct Target {
+    pos: Pos,
+    mean: f64,
+    var: f64,
+    damage: i64,
+    weight: f64,
+}
+
+/// 調査の候補
+struct Candidate {
+    pos: Pos,
+    mean: f64,
+    var: f64,
+    damage: i64,
+    is_on_route: bool,
+}
+
+/// 選択経路のコストの不確かさをどれだけ減らせるかで、調査する地点を選ぶ
+pub struct ProbePlanner<'a> {
+    estimator: &'a Estimator,
+    field: &'a HardnessField,
+    /// 経路を割る時と同じ叩き方でのコストの期待値
+    cost_model: &'a CostModel,
+    c: i64,
+    /// 調査で得られる観測のノイズの分散（log2）
+    probe_noise: f64,
+    /// 経路の近くのマスを、迂回路の候補として考慮する範囲
+    alternative_radius: i64,
+    /// 迂回路の候補となるマスの不確かさの重み
+    alternative_weight: f64,
+}
+
+impl<'a> ProbePlanner<'a> {
+    pub fn new(
+        estimator: &'a Estimator,
+        field: &'a HardnessField,
+        cost_model: &'a CostModel,
+        params: &Params,
+        c: i64,
+    ) -> ProbePlanner<'a> {
+        ProbePlanner {
+            estimator,
+            field,
+            cost_model,
+            c,
+            probe_noise: params.probe_noise,
+            alternative_radius: params.alternative_radius,
+            alternative_weight: params.alternative_weight,
+        }
+    }
+
+    /// 期待されるコストの削減量が調査のコストを上回る候補を、効率の良い順に予算の範囲で選ぶ
+    pub fn plan(
+        &self,
+        candidates: &[Pos],
+        grid: &Grid,
+        state: &State,
+        dp: &[i64],
+        budget: f64,
+    ) -> Vec<Pos> {
+        let ladder_top = *dp.last().unwrap();
+        let mut candidates: Vec<Candidate> = candidates
+            .iter()
+            .filter(|p| !state.is_broken.get(p) && state.damage.get(p) < ladder_top)
+            .map(|p| {
+                let e = self.field.get(p);
+                Candidate {
+                    pos: *p,
+                    mean: e.mean,
+                    var: e.std * e.std,
+                    damage: state.damage.get(p),
+                    is_on_route: grid.is_used.get(p),
+                }
+            })
+            .collect();
+        let mut targets = self.collect_targets(grid, state);
+
+        let mut plan = vec![];
+        let mut spent = 0.;
+        loop {
+            let mut best: Option<(f64, usize, f64)> = None;
+            for (i, cand) in candidates.iter().enumerate() {
+                let cost = self.expected_probe_cost(cand, dp);
+                if spent + cost > budget {
+                    continue;
+                }
+                let value = self.expected_gain(cand, &targets);
+                if value <= cost {
+                    continue;
+                }
+                let ratio = value / cost;
+                if best.is_none() || ratio > best.unwrap().0 {
+                    best = Some((ratio, i, cost));
+                }
+            }
+            let Some((_, i, cost)) = best else {
+                break;
+            };
+
+            let chosen = candidates.swap_remove(i);
+            spent += cost;
+            // 観測した後の分散は値によらないので、観測を待たずに更新できる
+            for t in targets.iter_mut() {
+                t.var = self.posterior_var(&t.pos, t.var, &chosen);
+            }
+            for cand in candidates.iter_mut() {
+                cand.var = self.posterior_var(&cand.pos, cand.var, &chosen);
+            }
+            plan.push(chosen.pos);
+        }
+        plan
+    }
+
+    fn collect_targets(&self, grid: &Grid, state: &State) -> Vec<Target> {
+        let mut is_target = Vec2d::new(grid.n, grid.m, false);
+        let mut targets = vec![];
+        for y in 0..grid.n as i64 {
+            for x in 0..grid.m as i64 {
+                let p = Pos { y, x };
+                if !grid.is_used.get(&p) || state.is_broken.get(&p) {
+                    continue;
+                }
+                // 経路上のマスと、迂回路になりうる周りのマス
+                let around =
+                    is_target.neighbors_in(&p, Neighborhood::Radius(self.alternative_radius));
+                for np in std::iter::once(p).chain(around) {
+                    if is_target.get(&np) || state.is_broken.get(&np) {
+                        continue;
+                    }
+                    let is_on_route = grid.is_used.get(&np);
+                    if !is_on_route && (np.y - y + np.x - x) % 2 != 0 {
+                        continue;
+                    }
+                    is_target.set(&np, true);
+                    let e = self.field.get(&np);
+                    targets.push(Target {
+                        pos: np,
+                        mean: e.mean,
+                        var: e.std * e.std,
+                        damage: state.damage.get(&np),
+                        weight: if is_on_route {
+                            1.
+                        } else {
+                            self.alternative_weight
+                        },
+                    });
+                }
+            }
+        }
+        targets
+    }
+
+    /// candを調査することで減らせる、マスを割るコストの不確かさ
+    fn expected_gain(&self, cand: &Candidate, targets: &[Target]) -> f64 {
+        let radius = 3. * self.estimator.length_scale;
+        targets
+            .iter()
+            .filter(|t| t.pos.euclid_dist(&cand.pos) <= radius)
+            .map(|t| {
+                let var = self.posterior_var(&t.pos, t.var, cand);
+                t.weight * (self.excavation_risk(t, t.var) - self.excavation_risk(t, var))
+            })
+            .sum()
+    }
+
+    /// 頑丈度の予測の分散がvarの時に、tを割るのにかかるコストの期待値
+    /// 分散が小さいほど無駄なく叩けるので、調査で分散を減らした分だけコストが減る
+    fn excavation_risk(&self, t: &Target, var: f64) -> f64 {
+        let estimate = Estimate {
+            mean: t.mean,
+            std: var.sqrt(),
+        };
+        self.cost_model.expected_cost(&estimate, t.damage)
+    }
+
+    /// candを観測した後のposの分散
+    fn posterior_var(&self, pos: &Pos, var: f64, cand: &Candidate) -> f64 {
+        let rho = self.estimator.correlation(pos, &cand.pos);
+        let cov = rho * (var * cand.var).sqrt();
+        f64::max(var - cov * cov / (cand.var + self.probe_noise), 1e-4)
+    }
+
+    /// dpの順に掘削した時の期待コスト
+    /// 選択経路上のマスに与えたパワーは、後で割る時に無駄にならない
+    fn expected_probe_cost(&self, cand: &Candidate, dp: &[i64]) -> f64 {
+        let std = cand.var.sqrt();
+        let mut cost = 0.;
+        let mut prev = cand.damage;
+        for &t in dp.iter().filter(|&&t| t > cand.damage) {
+            let p_unbroken = if prev == 0 {
+                1.
+            } else {
+                1. - normal_cdf(((prev as f64).log2() - cand.mean) / std)
+            };
+            let power = if cand.is_on_route { 0 } else { t - prev };
+            cost += p_unbroken * (self.c + power) as f64;
+            prev = t;
+        }
+        cost
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{def::*, params::Params};
+
+/// 入力のW, K, Cと調整用の定数から決める調査の方針
+#[derive(Debug, Clone)]
+pub struct SurveyPolicy {
+    /// 最初に調査する格子の間隔
+    pub survey_spacing: i64,
+    /// 最初の調査で、各地点に累積で与えるパワー
+    pub survey_ladder: Vec<i64>,
+    /// 選択経路の周りを調査する回数
+    pub refine_passes: usize,
+    /// 選択経路の周りで調査の候補とする格子の間隔
+    pub refine_spacing: i64,
+    /// 選択経路の周りの調査で、各地点に累積で与えるパワー
+    pub refine_ladder: Vec<i64>,
+    /// 調査する範囲を、家と水源を繋ぐ長方形からどれだけ広げるか
+    pub region_margin: i64,
+    /// 1回の調査に使うパワーの、選択経路の推定コストに対する割合
+    pub probe_budget_ratio: f64,
+}
+
+impl SurveyPolicy {
+    pub fn new(input: &Input, params: &Params) -> SurveyPolicy {
+        // Cが大きいほど1回の掘削が高くつくので、少ない回数で大きく叩き、調査も粗くする
+        let c_level = (input.c as f64).log2();
+
+        let survey_spacing =
+            (params.survey_spacing * (1. + c_level * params.survey_spacing_c_slope)).round() as i64;
+        let first_power = (params.probe_first_power
+            * (1. + c_level * params.probe_first_power_c_slope))
+            .round() as i64;
+        let ratio = params.probe_ladder_ratio + c_level * params.probe_ladder_c_slope;
+        let survey_top = (params.survey_top_power as f64
+            * (1. + c_level * params.survey_top_power_c_slope)) as i64;
+        let refine_top = (params.refine_top_power as f64
+            * (1. + c_level * params.refine_top_power_c_slope)) as i64;
+        let survey_ladder = geometric_ladder(first_power, ratio, survey_top);
+        let refine_ladder = geometric_ladder(first_power, ratio, refine_top);
+
+        // 家が多いほど選択経路が長くなり、調査する価値が大きい
+        // 水源が多いほど家から近い水源に繋げるので、選択経路が短くなり、調査する価値が小さい
+        let extra_sources = input.w.saturating_sub(1) as f64;
+        let refine_passes = (params.refine_passes_base
+            + input.k as f64 * params.refine_passes_per_house
+            - extra_sources * params.refine_passes_per_extra_source
+            - c_level * params.refine_passes_c_slope)
+            .round()
+            .clamp(
+                params.refine_passes_min as f64,
+                params.refine_passes_max as f64,
+            ) as usize;
+
+        SurveyPolicy {
+            survey_spacing,
+            survey_ladder,
+            refine_passes,
+            refine_spacing: survey_spacing / 2,
+            refine_ladder,
+            region_margin: survey_spacing,
+            probe_budget_ratio: params.probe_budget_ratio,
+        }
+    }
+}
+
+/// firstから始めてratio倍ずつ増やし、最後をtopにする
+fn geometric_ladder(first: i64, ratio: f64, top: i64) -> Vec<i64> {
+    let mut ladder = vec![];
+    let mut power = first as f64;
+    while (power as i64) < top {
+        ladder.push(power as i64);
+        power *= ratio;
+    }
+    ladder.push(top);
+    ladder
+}
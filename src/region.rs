@@ -0,0 +1,74 @@
+use crate::def::*;
+
+/// 家と水源を繋ぐ経路が通りうる領域
+///
+/// 各家から最も近い水源までと、水源を根とした家の最小全域木の辺について、
+/// 両端を含む長方形をmarginだけ広げた範囲の和集合とする
+pub struct Region {
+    mask: Vec2d<bool>,
+}
+
+impl Region {
+    pub fn new(input: &Input, margin: i64) -> Region {
+        let mut region = Region {
+            mask: Vec2d::new(input.n, input.m, false),
+        };
+
+        for h in input.house.iter() {
+            let nearest_source = input
+                .source
+                .iter()
+                .min_by_key(|s| s.manhattan_dist(h))
+                .unwrap();
+            region.add_corridor(h, nearest_source, margin);
+        }
+
+        // 水源をまとめて根としたPrim法
+        let k = input.house.len();
+        let mut in_tree = vec![false; k];
+        let mut best: Vec<(i64, Pos)> = input
+            .house
+            .iter()
+            .map(|h| {
+                let s = input
+                    .source
+                    .iter()
+                    .min_by_key(|s| s.manhattan_dist(h))
+                    .unwrap();
+                (s.manhattan_dist(h), *s)
+            })
+            .collect();
+        for _ in 0..k {
+            let i = (0..k)
+                .filter(|&i| !in_tree[i])
+                .min_by_key(|&i| best[i].0)
+                .unwrap();
+            in_tree[i] = true;
+            let h = input.house[i];
+            region.add_corridor(&h, &best[i].1, margin);
+            for j in 0..k {
+                let d = input.house[j].manhattan_dist(&h);
+                if !in_tree[j] && d < best[j].0 {
+                    best[j] = (d, h);
+                }
+            }
+        }
+
+        region
+    }
+
+    pub fn contains(&self, p: &Pos) -> bool {
+        p.is_valid(self.mask.n(), self.mask.m()) && self.mask.get(p)
+    }
+
+    fn add_corridor(&mut self, a: &Pos, b: &Pos, margin: i64) {
+        let (y0, y1) = (i64::min(a.y, b.y) - margin, i64::max(a.y, b.y) + margin);
+        let (x0, x1) = (i64::min(a.x, b.x) - margin, i64::max(a.x, b.x) + margin);
+        let (n, m) = (self.mask.n() as i64, self.mask.m() as i64);
+        for y in i64::max(0, y0)..=i64::min(n - 1, y1) {
+            for x in i64::max(0, x0)..=i64::min(m - 1, x1) {
+                self.mask.set(&Pos { y, x }, true);
+            }
+        }
+    }
+}
@@ -1,7 +1,19 @@
+use std::{collections::VecDeque, io::Write};
+
 use crate::{
+    beam::*,
+    clock::{Clock, Deadline},
+    cost_model::*,
     def::*,
+    estimator::*,
     grid::*,
     interactor::*,
+    params::*,
+    planner::*,
+    policy::*,
+    region::*,
+    steiner::*,
+    transcript::*,
     util::{rnd, time},
 };
 
@@ -10,82 +22,159 @@ struct Change {
     prev: bool,
 }
 
+/// 経路の焼きなましの近傍
+#[derive(Clone, Copy)]
+enum Move {
+    /// ランダムな家から水源までの経路を消して、繋ぎ直す
+    RipUpHousePath,
+    /// 選択経路上のランダムなマスの周りの経路を消して、繋ぎ直す
+    RipUpArea,
+    /// ランダムな家から水源までの経路を消して、別の水源に繋ぎ直す
+    SwapSource,
+}
+
 pub struct Solver {
     input: Input,
     state: State,
-    interactor: Interactor,
+    interactor: Box<dyn Interactor>,
+    /// 時間で処理を打ち切る時は、必ずこの時計を読む
+    clock: Clock,
+    transcript: Option<Transcript>,
+    phase: Phase,
+    params: Params,
+    policy: SurveyPolicy,
+    region: Region,
+    estimator: Estimator,
+    cost_model: CostModel,
 }
 
-fn pos_to_grid(y: i64, x: i64) -> Pos {
-    let y = if y == N as i64 { y - 1 } else { y };
-    let x = if x == N as i64 { x - 1 } else { x };
+/// 格子点(y, x)を、n行m列の盤面の端に寄せる
+fn pos_to_grid(y: i64, x: i64, n: usize, m: usize) -> Pos {
+    let y = y.clamp(0, n as i64 - 1);
+    let x = x.clamp(0, m as i64 - 1);
     Pos { y, x }
 }
 
-fn add_damage_to_hardness_if_needed(
-    p: &Pos,
-    hardness: i64,
-    state: &mut State,
-    interactor: &mut Interactor,
-) -> bool {
-    if state.is_broken.get(&p) {
-        return false;
+/// 長さlenの辺をspacingの間隔で区切る座標
+/// lenがspacingで割り切れなくても、最後の行（列）を含める
+fn grid_coords(len: usize, spacing: i64) -> Vec<i64> {
+    let mut coords: Vec<i64> = (0..len as i64).step_by(spacing as usize).collect();
+    if *coords.last().unwrap() != len as i64 - 1 {
+        coords.push(len as i64 - 1);
     }
-    let power = i64::min(S_MAX, hardness) - state.damage.get(&p);
-    if power <= 0 {
-        return false;
-    }
-    interactor.add_damage(&p, power, state);
-    return true;
+    coords
 }
 
 impl Solver {
-    pub fn new() -> Solver {
-        let mut interactor = Interactor::new();
+    pub fn new(mut interactor: Box<dyn Interactor>, mut params: Params) -> Solver {
         let input = interactor.read_input();
-        let state = State::new(input.n);
+        let clock = interactor.clock();
+        params.scale_time_limits(input.n, input.m);
+        let state = State::new(input.n, input.m);
+        let policy = SurveyPolicy::new(&input, &params);
+        let estimator = Estimator::new(&params);
+        let region = Region::new(&input, policy.region_margin);
+        let cost_model = CostModel::new(&params, input.c);
 
         Solver {
             input,
             state,
             interactor,
+            clock,
+            transcript: None,
+            phase: Phase::Survey,
+            params,
+            policy,
+            region,
+            estimator,
+            cost_model,
         }
     }
 
-    pub fn solve(&mut self) {
-        // TODO: 必要な箇所だけを、house、sourceの位置をもとに計算する
-        // グリッド上にあらかじめ掘削し、頑丈度を調べる
-        for y in (0..=N as i64).step_by(20) {
-            for x in (0..=N as i64).step_by(20) {
-                let p = pos_to_grid(y, x);
-                self.investigate(&p, &vec![13, 50, 100]);
+    /// 掘削とジャッジの応答と、時計から読んだ時刻をファイルに記録する
+    pub fn record_transcript(&mut self, path: &str) {
+        self.transcript = Some(Transcript::create(path, &self.input));
+        self.clock.start_recording();
+    }
+
+    /// 全ての家に水が流れたらAllConnected、不正な掘削をしたらInvalidを返す
+    pub fn solve(&mut self) -> Response {
+        let response = match self.run() {
+            Ok(()) => Response::Broken,
+            Err(response) => response,
+        };
+
+        self.state.output_state("log/state_final.txt");
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record_clock(&self.clock.take_readings());
+            transcript.flush();
+        }
+        std::io::stdout().flush().unwrap();
+
+        match response {
+            Response::AllConnected => {}
+            Response::Invalid => eprintln!("Invalid operation"),
+            _ => eprintln!("Finished excavation, but some houses are not connected"),
+        }
+        eprintln!("elapsed seconds: {:.4}", time::elapsed_seconds());
+        eprintln!("total power: {}", self.state.total_damage);
+        response
+    }
+
+    fn run(&mut self) -> Result<(), Response> {
+        // 家と水源を繋ぐのに関係する範囲のグリッド上で、あらかじめ掘削し頑丈度を調べる
+        self.phase = Phase::Survey;
+        let policy = self.policy.clone();
+        let (n, m) = (self.input.n, self.input.m);
+        for &y in grid_coords(n, policy.survey_spacing).iter() {
+            for &x in grid_coords(m, policy.survey_spacing).iter() {
+                let p = Pos { y, x };
+                if !self.region.contains(&p) {
+                    continue;
+                }
+                self.investigate(&p, &policy.survey_ladder)?;
             }
         }
-        let ds = vec![20, 20, 20, 20, 20, 20, 20];
 
-        for (i, d) in ds.iter().enumerate() {
+        for i in 0..policy.refine_passes {
+            // 時間が足りなければ、残りの調査はせずに最後の経路を選ぶ
+            if self.clock.elapsed_seconds() >= self.params.refine_time_limit {
+                break;
+            }
+
             // 頑丈度を予測したグリッドを作成する
-            let mut estimated_grid = self.generate_estimated_grid();
+            let field = self.estimate_hardness_field();
+            let mut estimated_grid = self.generate_estimated_grid(&field);
 
-            // 山登りによる選択経路の最適化
-            self.generate_route(&mut estimated_grid);
+            // シュタイナー木で経路を選ぶ
+            self.generate_route(&mut estimated_grid, self.params.refine_time_limit);
 
             estimated_grid.output_grid(format!("log/grid_{}.txt", i).as_str());
             self.state
                 .output_state(format!("log/state_{}.txt", i).as_str());
 
-            let dp = vec![13, 50, 100, 300, 500];
-
             // 選択経路の周りを探索する
-            self.investigate_around_used_path(&estimated_grid, *d / 2, &dp);
+            self.phase = Phase::Investigate;
+            self.investigate_around_used_path(
+                &estimated_grid,
+                &field,
+                policy.refine_spacing,
+                &policy.refine_ladder,
+            )?;
         }
 
-        let mut estimated_grid = self.generate_estimated_grid();
-        self.optimize_route(&mut estimated_grid);
+        let field = self.estimate_hardness_field();
+        let mut estimated_grid = self.generate_estimated_grid(&field);
+        if self.params.route_beam_width > 0 {
+            self.generate_route_by_beam_search(&mut estimated_grid);
+        } else {
+            self.generate_route(&mut estimated_grid, self.params.optimize_time_limit);
+            self.optimize_route(&mut estimated_grid, self.params.optimize_time_limit);
+        }
 
-        estimated_grid.output_grid(format!("log/grid_{}.txt", ds.len()).as_str());
+        estimated_grid.output_grid(format!("log/grid_{}.txt", policy.refine_passes).as_str());
         self.state
-            .output_state(format!("log/state_{}.txt", ds.len()).as_str());
+            .output_state(format!("log/state_{}.txt", policy.refine_passes).as_str());
 
         if cfg!(feature = "local") {
             println!("# end optimize");
@@ -96,232 +185,547 @@ impl Solver {
         }
 
         // 選択経路に使われている地点を割る
-        self.destroy_used_path(&estimated_grid);
+        self.phase = Phase::Destroy;
+        self.destroy_used_path(estimated_grid, field)
     }
 
-    fn generate_route(&self, estimated_grid: &mut Grid) {
-        for h_pos in self.input.house.iter() {
-            let (nearest_source_path, _) = estimated_grid.find_path_to_nearest_source(
-                &h_pos,
-                INF,
-                &self.input.source,
-                self.input.c,
-            );
-            for p in nearest_source_path.iter() {
-                estimated_grid.set(p, true);
-            }
+    /// 時刻time_limit（秒）までに、estimated_gridに経路を作る
+    fn generate_route(&self, estimated_grid: &mut Grid, time_limit: f64) {
+        // 家ごとの最短路ではなく、経路を共有するシュタイナー木で繋ぐ
+        let tree = SteinerSolver::new(estimated_grid).solve(
+            self.params.dreyfus_wagner_max_houses,
+            self.params.route_random_restarts,
+            self.params.key_path_exchange_passes,
+            &Deadline::new(&self.clock, time_limit),
+        );
+        for p in tree.iter() {
+            estimated_grid.set(p, true);
         }
     }
 
-    fn optimize_route(&self, estimated_grid: &mut Grid) {
-        // 初期解の作成
-        self.generate_route(estimated_grid);
+    /// generate_routeとoptimize_routeの代わりに、ビームサーチで経路を作る
+    /// 割る途中で選び直す時は時間が足りないので、今の経路からoptimize_routeで選び直す
+    fn generate_route_by_beam_search(&self, estimated_grid: &mut Grid) {
+        let deadline = Deadline::new(&self.clock, self.params.optimize_time_limit);
+        let tree = BeamSearch::new(estimated_grid).solve(self.params.route_beam_width, &deadline);
+        let tree = SteinerSolver::new(estimated_grid).improve_by_key_path_exchange(
+            tree,
+            self.params.key_path_exchange_passes,
+            &deadline,
+        );
+        for p in tree.iter() {
+            estimated_grid.set(p, true);
+        }
+        if cfg!(feature = "local") {
+            eprintln!(
+                "generate_route_by_beam_search: route_cost = {}",
+                estimated_grid.total_score
+            );
+        }
+    }
 
+    /// 時刻time_limit（秒）まで、estimated_gridの経路を初期解として最適化する
+    fn optimize_route(&self, estimated_grid: &mut Grid, time_limit: f64) {
         let mut current_score = estimated_grid.total_score;
+        let mut best_score = current_score;
+        let mut best_is_used = estimated_grid.is_used.clone();
 
-        // 山登りによる最適化
-        while time::elapsed_seconds() < 4. {
-            // ランダムな家から接続している水源までのパスを消す
-            let h_pos = &self.input.house[rnd::gen_range(0, self.input.house.len())];
-            let (path_to_source, _) = estimated_grid.find_current_path_to_source(&h_pos).unwrap();
+        // 水源が1つなら、別の水源に繋ぎ直す近傍は使えない
+        let mut moves = vec![Move::RipUpHousePath, Move::RipUpArea];
+        if self.input.source.len() > 1 {
+            moves.push(Move::SwapSource);
+        }
 
-            let mut changes = vec![];
-            for p in path_to_source.iter() {
-                changes.push(Change {
-                    p: p.clone(),
-                    prev: estimated_grid.is_used.get(p),
-                });
-                estimated_grid.set(p, false);
+        // 焼きなましによる最適化
+        let start_time = self.clock.elapsed_seconds();
+        let end_time = f64::max(start_time, time_limit);
+        let mut iteration = 0;
+        loop {
+            let elapsed = self.clock.elapsed_seconds();
+            if elapsed >= end_time {
+                break;
             }
+            iteration += 1;
+            let progress = (elapsed - start_time) / (end_time - start_time);
+            let temp = self.params.anneal_start_temp.powf(1. - progress)
+                * self.params.anneal_end_temp.powf(progress);
 
-            // 水源に接続されなくなった家を再度接続する
-            // TODO: 経路を消した家を最初に再接続する
-            let mut reconnect_houses = estimated_grid.find_unconnected_houses();
-            rnd::shuffle(&mut reconnect_houses);
-
-            for i in reconnect_houses.iter() {
-                let (nearest_source_path, _) = estimated_grid.find_path_to_nearest_source(
-                    &self.input.house[*i],
-                    INF,
-                    &self.input.source,
-                    self.input.c,
-                );
-                for p in nearest_source_path.iter() {
-                    changes.push(Change {
-                        p: p.clone(),
-                        prev: estimated_grid.is_used.get(p),
-                    });
-                    estimated_grid.set(p, true);
+            let mut changes = vec![];
+            let mut ripped_house = None;
+            let mut excluded_source = None;
+            match moves[rnd::gen_range(0, moves.len())] {
+                Move::RipUpHousePath => {
+                    ripped_house = self
+                        .rip_up_house_path(estimated_grid, &mut changes)
+                        .map(|(i, _)| i);
+                }
+                Move::RipUpArea => self.rip_up_area(estimated_grid, &mut changes),
+                Move::SwapSource => {
+                    if let Some((i, source)) = self.rip_up_house_path(estimated_grid, &mut changes)
+                    {
+                        ripped_house = Some(i);
+                        excluded_source = Some(source);
+                    }
                 }
             }
+            if !self.reconnect_houses(estimated_grid, ripped_house, excluded_source, &mut changes) {
+                Self::rollback(estimated_grid, changes);
+                continue;
+            }
 
             let new_score = estimated_grid.total_score;
-
-            if new_score < current_score {
-                // 採用
-                eprintln!("{} -> {}", current_score, new_score);
+            let delta = (new_score - current_score) as f64;
+            if delta <= 0. || rnd::nextf() < (-delta / temp).exp() {
                 current_score = new_score;
-            } else {
-                // ロールバック
-                changes.reverse();
-                for c in changes.iter() {
-                    estimated_grid.set(&c.p, c.prev);
+                if current_score < best_score {
+                    best_score = current_score;
+                    best_is_used = estimated_grid.is_used.clone();
                 }
+            } else {
+                Self::rollback(estimated_grid, changes);
             }
         }
+
+        // 最も良かった解に戻す
+        for (p, &is_used) in best_is_used.iter() {
+            estimated_grid.set(&p, is_used);
+        }
+        if cfg!(feature = "local") {
+            eprintln!(
+                "optimize_route: iteration = {}, route_cost = {}",
+                iteration, best_score
+            );
+        }
     }
 
-    fn destroy_used_path(&mut self, estimated_grid: &Grid) {
-        for y in 0..N as i64 {
-            for x in 0..N as i64 {
+    /// ランダムな家から、繋がっている水源までの経路を消す
+    /// 家と水源のマスは残し、経路を消した家の添字と繋がっていた水源を返す
+    fn rip_up_house_path(
+        &self,
+        grid: &mut Grid,
+        changes: &mut Vec<Change>,
+    ) -> Option<(usize, Pos)> {
+        let i = rnd::gen_range(0, self.input.house.len());
+        let (path_to_source, _) = grid.find_current_path_to_source(&self.input.house[i])?;
+        for p in path_to_source.iter() {
+            self.unset_route_cell(grid, p, changes);
+        }
+        path_to_source.last().map(|s| (i, *s))
+    }
+
+    /// 選択経路上のランダムなマスの周りの正方形に含まれる経路を消す
+    fn rip_up_area(&self, grid: &mut Grid, changes: &mut Vec<Change>) {
+        let used: Vec<Pos> = grid
+            .is_used
+            .iter()
+            .filter(|(_, &is_used)| is_used)
+            .map(|(p, _)| p)
+            .collect();
+        let center = used[rnd::gen_range(0, used.len())];
+        let r = rnd::gen_range(
+            self.params.rip_up_radius_min,
+            self.params.rip_up_radius_max + 1,
+        ) as i64;
+        for y in center.y - r..=center.y + r {
+            for x in center.x - r..=center.x + r {
                 let p = Pos { y, x };
-                // FIXME: is_usedに常にhouse、sourceが含まれるように修正
-                if !estimated_grid.is_used.get(&p)
-                    && !self.input.house.contains(&p)
-                    && !self.input.source.contains(&p)
-                {
+                if p.is_valid(grid.n, grid.m) && grid.is_used.get(&p) {
+                    self.unset_route_cell(grid, &p, changes);
+                }
+            }
+        }
+    }
+
+    /// 家と水源以外のマスを経路から外し、行き止まりになったマスも外す
+    fn unset_route_cell(&self, grid: &mut Grid, p: &Pos, changes: &mut Vec<Change>) {
+        let is_terminal = |p: &Pos| self.input.house.contains(p) || self.input.source.contains(p);
+        let mut stack = vec![*p];
+        while let Some(p) = stack.pop() {
+            if !grid.is_used.get(&p) || is_terminal(&p) {
+                continue;
+            }
+            changes.push(Change { p, prev: true });
+            grid.set(&p, false);
+            for np in grid.is_used.neighbors(&p) {
+                if !grid.is_used.get(&np) {
                     continue;
                 }
-                let mut estimated_hardness = i64::max(
-                    10,
-                    (self.estimate_hardness(&p).unwrap() as f64 * 0.8) as i64,
-                );
-                while !self.state.is_broken.get(&p) {
-                    add_damage_to_hardness_if_needed(
-                        &p,
-                        estimated_hardness,
-                        &mut self.state,
-                        &mut self.interactor,
-                    );
-                    // param:
-                    estimated_hardness = i64::min(S_MAX, (estimated_hardness as f64 * 1.2) as i64);
+                let degree = grid
+                    .is_used
+                    .neighbors(&np)
+                    .filter(|q| grid.is_used.get(q))
+                    .count();
+                if degree <= 1 {
+                    stack.push(np);
+                }
+            }
+        }
+    }
+
+    /// 水源に繋がっていない家を、ランダムな順に水に繋がっているマスへ繋ぐ
+    /// ripped_houseが繋がっていなければ、他の家より先に繋ぐ
+    /// excluded_sourceが指定されていれば、ripped_houseはその水源以外の水源へ直接繋ぐ
+    fn reconnect_houses(
+        &self,
+        grid: &mut Grid,
+        ripped_house: Option<usize>,
+        excluded_source: Option<Pos>,
+        changes: &mut Vec<Change>,
+    ) -> bool {
+        let mut reconnect_houses = grid.find_unconnected_houses();
+        rnd::shuffle(&mut reconnect_houses);
+        // 経路を消した家を最初に再接続する
+        if let Some(j) = reconnect_houses
+            .iter()
+            .position(|&i| Some(i) == ripped_house)
+        {
+            reconnect_houses.swap(0, j);
+        }
+
+        for i in reconnect_houses.iter() {
+            let h_pos = &self.input.house[*i];
+            let path = match excluded_source {
+                // 別の水源に繋ぎ直す時は、水源まで直接繋ぐ
+                Some(excluded_source) if Some(*i) == ripped_house => {
+                    let source: Vec<Pos> = self
+                        .input
+                        .source
+                        .iter()
+                        .filter(|s| **s != excluded_source)
+                        .cloned()
+                        .collect();
+                    grid.find_path_to_nearest_source(h_pos, INF, &source)
+                        .map(|(path, _)| path)
                 }
+                _ => grid.find_path_to_water(h_pos, INF).map(|(path, _)| path),
+            };
+            let Some(path) = path else {
+                return false;
+            };
+            for p in path.iter() {
+                changes.push(Change {
+                    p: *p,
+                    prev: grid.is_used.get(p),
+                });
+                grid.set(p, true);
             }
         }
+        true
     }
 
-    fn investigate_around_used_path(&mut self, estimated_grid: &Grid, d: i64, dp: &Vec<i64>) {
+    fn rollback(grid: &mut Grid, mut changes: Vec<Change>) {
+        changes.reverse();
+        for c in changes.iter() {
+            grid.set(&c.p, c.prev);
+        }
+    }
+
+    fn destroy_used_path(
+        &mut self,
+        mut estimated_grid: Grid,
+        mut field: HardnessField,
+    ) -> Result<(), Response> {
+        // 水源から経路に沿って外側へ割り、割ったマスの頑丈度をすぐに次のマスの予測に使う
+        // 予測よりコストがかかっている時は、割っていない部分の経路を選び直す
+        'reroute: loop {
+            let mut expected_cost = 0.;
+            let mut observed_cost = 0;
+            let order = self.excavation_order(&estimated_grid);
+            for (i, &p) in order.iter().enumerate() {
+                if self.state.is_broken.get(&p) {
+                    continue;
+                }
+                self.estimator.update(&mut field, &self.state.intervals());
+                let estimate = self.estimator.predict(&field, &p);
+                let plan = self.cost_model.plan(&estimate, self.state.damage.get(&p));
+                // 経路を選んだ時に見込んでいたコストと比べる
+                expected_cost += estimated_grid.estimated_weight.get(&p) as f64;
+                for hardness in plan.targets {
+                    if self.state.is_broken.get(&p) {
+                        break;
+                    }
+                    let damage = self.state.damage.get(&p);
+                    if self.add_damage_to_hardness_if_needed(&p, hardness)? {
+                        observed_cost += self.input.c + self.state.damage.get(&p) - damage;
+                    }
+                }
+
+                let remaining = &order[i + 1..];
+                if self.should_reroute(expected_cost, observed_cost, remaining) {
+                    (estimated_grid, field) = self.reroute(&estimated_grid, remaining);
+                    continue 'reroute;
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// 前回経路を選んでからのコストが、予測から閾値を超えてずれていて、選び直す時間があるか
+    fn should_reroute(&self, expected_cost: f64, observed_cost: i64, remaining: &[Pos]) -> bool {
+        let excess = observed_cost as f64 - expected_cost;
+        excess > self.params.reroute_min_excess
+            && observed_cost as f64 > expected_cost * self.params.reroute_ratio
+            && self.clock.elapsed_seconds() < self.reroute_time_limit(remaining)
+    }
+
+    /// 今の経路の残りのマスを割る時間を残した、経路を選び直せる時刻
+    fn reroute_time_limit(&self, remaining: &[Pos]) -> f64 {
+        let cells = remaining
+            .iter()
+            .filter(|p| !self.state.is_broken.get(p))
+            .count();
+        self.params.reroute_time_limit - cells as f64 * self.params.excavation_time_per_cell
+    }
+
+    /// 割ったマスの観測を反映した予測で、経路を選び直す
+    /// 割ったマスのコストは0なので、割っていない部分だけを選び直すことになる
+    ///
+    /// 経路を作り直すとreroute_timeに収まらないので、今の経路を新しい予測の上で初期解にする
+    fn reroute(&self, current_grid: &Grid, remaining: &[Pos]) -> (Grid, HardnessField) {
+        let field = self.estimate_hardness_field();
+        let mut estimated_grid = self.generate_estimated_grid(&field);
+        for p in current_grid.used_cells() {
+            estimated_grid.set(p, true);
+        }
+        let time_limit = f64::min(
+            self.clock.elapsed_seconds() + self.params.reroute_time,
+            self.reroute_time_limit(remaining),
+        );
+        self.optimize_route(&mut estimated_grid, time_limit);
+        if cfg!(feature = "local") {
+            eprintln!(
+                "reroute at {:.3}s: route_cost = {}",
+                time::elapsed_seconds(),
+                estimated_grid.total_score
+            );
+        }
+        (estimated_grid, field)
+    }
+
+    /// 選択経路のマスを、水源から経路に沿った幅優先探索の順に並べる
+    fn excavation_order(&self, estimated_grid: &Grid) -> Vec<Pos> {
+        // FIXME: is_usedに常にhouse、sourceが含まれるように修正
+        let is_route = |p: &Pos| {
+            estimated_grid.is_used.get(p)
+                || self.input.house.contains(p)
+                || self.input.source.contains(p)
+        };
+        let (n, m) = (self.input.n, self.input.m);
+        let mut seen = Vec2d::new(n, m, false);
+        let mut order = vec![];
+        let mut queue = VecDeque::new();
+        for s in self.input.source.iter() {
+            seen.set(s, true);
+            queue.push_back(*s);
+        }
+        while let Some(p) = queue.pop_front() {
+            order.push(p);
+            for np in seen.neighbors(&p) {
+                if !seen.get(&np) && is_route(&np) {
+                    seen.set(&np, true);
+                    queue.push_back(np);
+                }
+            }
+        }
+
+        // 水源から辿れないマスも割る
+        for y in 0..self.input.n as i64 {
+            for x in 0..self.input.m as i64 {
+                let p = Pos { y, x };
+                if is_route(&p) && !seen.get(&p) {
+                    order.push(p);
+                }
+            }
+        }
+        order
+    }
+
+    fn investigate_around_used_path(
+        &mut self,
+        estimated_grid: &Grid,
+        field: &HardnessField,
+        d: i64,
+        dp: &[i64],
+    ) -> Result<(), Response> {
         let mut investigate_pos = vec![];
 
-        for y in 0..N as i64 {
-            for x in 0..N as i64 {
+        // 探索箇所の候補（盤面の外は端に寄せるので、offsetsを直接使う）
+        let offsets: Vec<(i64, i64)> = std::iter::once((0, 0))
+            .chain(Neighborhood::Radius(d).offsets().iter().copied())
+            .collect();
+        for y in 0..self.input.n as i64 {
+            for x in 0..self.input.m as i64 {
                 let p = Pos { y, x };
                 if !estimated_grid.is_used.get(&p) {
                     continue;
                 }
-                // 探索箇所を加える
-                for py in y - d..=y + d {
-                    for px in x - d..=x + d {
-                        if (py % d) != 0 || (px % d) != 0 {
-                            continue;
-                        }
-                        if ((px + py) % (2 * d)) == d {
-                            continue;
-                        }
-                        let np = pos_to_grid(py, px);
-                        if !np.is_valid() || investigate_pos.contains(&np) {
-                            continue;
-                        }
-                        investigate_pos.push(np);
+                for &(dy, dx) in offsets.iter() {
+                    let (py, px) = (y + dy, x + dx);
+                    if (py % d) != 0 || (px % d) != 0 {
+                        continue;
+                    }
+                    if ((px + py) % (2 * d)) == d {
+                        continue;
+                    }
+                    let np = pos_to_grid(py, px, self.input.n, self.input.m);
+                    if !self.region.contains(&np) || investigate_pos.contains(&np) {
+                        continue;
                     }
+                    investigate_pos.push(np);
                 }
             }
         }
 
-        for p in investigate_pos.iter() {
-            self.investigate(&p, &dp);
+        // 経路のコストの不確かさを減らせる地点を、予算の範囲で選ぶ
+        let planner = ProbePlanner::new(
+            &self.estimator,
+            field,
+            &self.cost_model,
+            &self.params,
+            self.input.c,
+        );
+        let budget = estimated_grid.total_score as f64 * self.policy.probe_budget_ratio;
+        let plan = planner.plan(&investigate_pos, estimated_grid, &self.state, dp, budget);
+
+        for p in plan.iter() {
+            self.investigate(p, dp)?;
         }
+        Ok(())
     }
 
-    fn investigate(&mut self, p: &Pos, dp: &Vec<i64>) -> bool {
+    fn investigate(&mut self, p: &Pos, dp: &[i64]) -> Result<bool, Response> {
         if self.state.is_broken.get(p) {
-            return true;
+            return Ok(true);
         }
 
         for dp in dp.iter() {
-            add_damage_to_hardness_if_needed(p, *dp, &mut self.state, &mut self.interactor);
+            self.add_damage_to_hardness_if_needed(p, *dp)?;
+        }
+        Ok(false)
+    }
+
+    fn add_damage_to_hardness_if_needed(
+        &mut self,
+        p: &Pos,
+        hardness: i64,
+    ) -> Result<bool, Response> {
+        if self.state.is_broken.get(p) {
+            return Ok(false);
+        }
+        let power = i64::min(S_MAX, hardness) - self.state.damage.get(p);
+        if power <= 0 {
+            return Ok(false);
+        }
+        let response = self.interactor.add_damage(p, power, &mut self.state);
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record_clock(&self.clock.take_readings());
+            transcript.record(p, power, response, self.phase);
+        }
+        // 終了を表す応答は、Errとして呼び出し元まで戻す
+        match response {
+            Response::NotBroken | Response::Broken => Ok(true),
+            response => Err(response),
         }
-        return false;
     }
 
-    fn generate_estimated_grid(&self) -> Grid {
+    fn generate_estimated_grid(&self, field: &HardnessField) -> Grid {
         // TODO: is_usedにhouseとsourceの位置を追加
-        let mut estimated_weight = Vec2d::new(N, N, 0);
-        for y in 0..N as i64 {
-            for x in 0..N as i64 {
-                let p = pos_to_grid(y, x);
-                let w = self.estimate_hardness(&p).unwrap_or(10) - self.state.damage.get(&p);
-                estimated_weight.set(&p, i64::max(0, w));
+        let (n, m) = (self.input.n, self.input.m);
+        let mut estimated_weight = Vec2d::new(n, m, 0);
+        for y in 0..n as i64 {
+            for x in 0..m as i64 {
+                let p = Pos { y, x };
+                let w = if self.state.is_broken.get(&p) {
+                    0.
+                } else {
+                    self.cost_model
+                        .expected_cost(&field.get(&p), self.state.damage.get(&p))
+                };
+                estimated_weight.set(&p, w.round() as i64);
             }
         }
-        let mut is_used = Vec2d::new(N, N, false);
+        let mut is_used = Vec2d::new(n, m, false);
         for p in self.input.house.iter() {
-            is_used.set(&p, true);
+            is_used.set(p, true);
         }
         for p in self.input.source.iter() {
-            is_used.set(&p, true);
+            is_used.set(p, true);
         }
 
-        Grid {
-            total_score: 0,
+        Grid::new(
             estimated_weight,
             is_used,
-            house: self.input.house.clone(),
-            source: self.input.source.clone(),
-        }
+            self.input.house.clone(),
+            self.input.source.clone(),
+        )
+    }
+
+    fn estimate_hardness_field(&self) -> HardnessField {
+        self.estimator
+            .estimate(&self.state.intervals(), self.input.n, self.input.m)
     }
+}
 
-    fn estimate_hardness(&self, pos: &Pos) -> Option<i64> {
-        if self.state.is_broken.get(pos) {
-            return self.fetch_investigated_hardness(pos);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::judge::Judge;
+
+    #[test]
+    fn solver_connects_all_houses_on_small_board() {
+        // 時間で打ち切る処理は、すぐに打ち切る
+        let params = Params {
+            optimize_time_limit: 0.,
+            reroute_time_limit: 0.,
+            ..Params::default()
+        };
+        for seed in 0..4 {
+            let judge = Judge::generate_with_size(seed, 20, 20);
+            let interactor = Box::new(InMemoryInteractor::from_judge(judge));
+            let mut solver = Solver::new(interactor, params.clone());
+            assert_eq!(solver.solve(), Response::AllConnected, "seed = {}", seed);
         }
+    }
 
-        const D: i64 = 5;
-        let mut sum = 0.;
-        let mut div = 0.;
+    #[test]
+    fn solver_connects_all_houses_on_rectangular_board() {
+        for (seed, n, m) in [(0, 12, 30), (1, 30, 12), (2, 5, 40)] {
+            let judge = Judge::generate_with_size(seed, n, m);
+            let interactor = Box::new(InMemoryInteractor::from_judge(judge));
+            let mut solver = Solver::new(interactor, Params::default());
+            assert_eq!(
+                solver.solve(),
+                Response::AllConnected,
+                "seed = {}, {}x{}",
+                seed,
+                n,
+                m
+            );
 
-        for x in (0..=N as i64).step_by(D as usize) {
-            for y in (0..=N as i64).step_by(D as usize) {
-                let p = pos_to_grid(y, x);
-                if !p.is_valid() || pos == &p {
-                    continue;
-                }
-                let d = pos.euclid_dist(&p);
-                if d > 20. {
-                    continue;
-                }
-                let w = 1. / d;
-                if let Some(h) = self.fetch_investigated_hardness(&p) {
-                    sum += (h as f64).log2() * w * w;
-                    div += w * w;
+            // 最後の行と列を含め、領域内の調査の格子点は全て掘削している
+            let spacing = solver.policy.survey_spacing;
+            for &y in grid_coords(n, spacing).iter() {
+                for &x in grid_coords(m, spacing).iter() {
+                    let p = Pos { y, x };
+                    if solver.region.contains(&p) {
+                        assert!(solver.state.damage.get(&p) > 0, "{:?} is not probed", p);
+                    }
                 }
             }
         }
-        if sum == 0. {
-            None
-        } else {
-            Some((2.0 as f64).powf(sum / div).round() as i64)
-        }
     }
 
-    fn fetch_investigated_hardness(&self, p: &Pos) -> Option<i64> {
-        // まだ掘削していない場合
-        if self.state.damage.get(p) == 0 {
-            return None;
-        }
-        // 調査済みで、まだ壊れていなかったら、与えたダメージの5倍を返す
-        if !self.state.is_broken.get(p) {
-            return Some(self.state.damage.get(p) * 5);
-        }
-
-        let damage_before_break = self.state.damage_before_break.get(p);
-        let damage = self.state.damage.get(p);
+    #[test]
+    fn grid_coords_include_last_row() {
+        assert_eq!(grid_coords(12, 5), vec![0, 5, 10, 11]);
+        assert_eq!(grid_coords(11, 5), vec![0, 5, 10]);
+        assert_eq!(grid_coords(3, 5), vec![0, 2]);
+    }
 
-        let a = if damage_before_break == 0 {
-            (damage as f64 * 0.5) as i64
-        } else {
-            (damage_before_break + damage) / 2
-        };
-        Some(a)
+    #[test]
+    fn pos_to_grid_clamps_outside_points() {
+        assert_eq!(pos_to_grid(12, 40, 12, 30), Pos { y: 11, x: 29 });
+        assert_eq!(pos_to_grid(-4, 5, 12, 30), Pos { y: 0, x: 5 });
+        assert_eq!(pos_to_grid(3, 5, 12, 30), Pos { y: 3, x: 5 });
     }
 }
@@ -0,0 +1,605 @@
+use std::{cell::RefCell, cmp::Reverse, collections::BinaryHeap, collections::VecDeque};
+
+use crate::{clock::Deadline, def::*, dijkstra::Dijkstra, grid::Grid, util::rnd};
+
+/// 近似解を作る時に、家を木に繋ぐ順番
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HouseOrder {
+    /// 水源から近い（推定コストが小さい）家から繋ぐ
+    NearestFirst,
+    /// 水源から遠い家から繋ぐ
+    FarthestFirst,
+    /// ランダムな順に繋ぐ
+    Random,
+    /// 毎回、今の木から最も近い家を繋ぐ
+    Prim,
+}
+
+/// 全ての家をいずれかの水源に繋ぐ、マスの重みの和が最小の木（シュタイナー木）を求める
+///
+/// マスの重みは`Grid::cell_cost`で、水源はまとめて1つの端点として扱う
+pub struct SteinerSolver<'a> {
+    grid: &'a Grid,
+    dijkstra: RefCell<Dijkstra>,
+}
+
+impl<'a> SteinerSolver<'a> {
+    pub fn new(grid: &'a Grid) -> SteinerSolver<'a> {
+        SteinerSolver {
+            grid,
+            dijkstra: RefCell::new(Dijkstra::new(grid.n, grid.m, Neighborhood::Four)),
+        }
+    }
+
+    /// 家の数がdreyfus_wagner_max_houses以下なら厳密解、それより多ければ近似解を求め、木に含まれるマスを返す
+    ///
+    /// 近似解は家を繋ぐ順番を変えて、決まった順番とrandom_restarts回のランダムな順番で作り、最も安いものを改善する
+    /// 鍵パスの交換はexchange_passes周まで行う
+    /// 期限を過ぎたら、それまでに作った木で打ち切る（Primの順番の木は必ず作る）
+    pub fn solve(
+        &self,
+        dreyfus_wagner_max_houses: usize,
+        random_restarts: usize,
+        exchange_passes: usize,
+        deadline: &Deadline,
+    ) -> Vec<Pos> {
+        if self.grid.house.len() <= dreyfus_wagner_max_houses {
+            return self.improve_by_key_path_exchange(
+                self.dreyfus_wagner(),
+                exchange_passes,
+                deadline,
+            );
+        }
+        let mut orders = vec![HouseOrder::NearestFirst, HouseOrder::FarthestFirst];
+        orders.extend(std::iter::repeat_n(HouseOrder::Random, random_restarts));
+        let mut tree = self.build(HouseOrder::Prim);
+        for order in orders {
+            if deadline.is_passed() {
+                break;
+            }
+            let other = self.build(order);
+            if self.tree_cost(&other) < self.tree_cost(&tree) {
+                tree = other;
+            }
+        }
+        // 鍵パスの交換は重いので、最も安い初期解にだけ行う
+        self.improve_by_key_path_exchange(tree, exchange_passes, deadline)
+    }
+
+    /// orderの順に家を繋いだ木
+    pub fn build(&self, order: HouseOrder) -> Vec<Pos> {
+        let mut houses = self.grid.house.clone();
+        match order {
+            HouseOrder::Prim => return self.shortest_path_heuristic(),
+            HouseOrder::NearestFirst | HouseOrder::FarthestFirst => {
+                self.search(&self.grid.source, |_| false);
+                let dijkstra = self.dijkstra.borrow();
+                houses.sort_by_key(|h| dijkstra.dist(h));
+                if order == HouseOrder::FarthestFirst {
+                    houses.reverse();
+                }
+            }
+            HouseOrder::Random => rnd::shuffle(&mut houses),
+        }
+        self.connect_in_order(&houses)
+    }
+
+    fn tree_cost(&self, tree: &[Pos]) -> i64 {
+        tree.iter().map(|p| self.cost(p)).sum()
+    }
+
+    fn cost(&self, p: &Pos) -> i64 {
+        self.grid.cell_cost(p)
+    }
+
+    /// dp[S][v]: 端点の集合Sとvを繋ぐ木の最小コスト（O(3^K nm)）
+    ///
+    /// 家が別々の水源に繋がってもよいので、水源を含む木を家の集合ごとに組み合わせた森の最小コストを答えとする
+    fn dreyfus_wagner(&self) -> Vec<Pos> {
+        #[derive(Clone, Copy)]
+        enum From {
+            None,
+            Terminal,
+            Merge(usize),
+            Edge(Pos),
+        }
+        #[derive(Clone, Copy)]
+        enum ForestFrom {
+            None,
+            Tree(Pos),
+            Split(usize),
+        }
+
+        let t = self.grid.house.len() + 1;
+        let full = (1 << t) - 1;
+        let mut dp = vec![Vec2d::new(self.grid.n, self.grid.m, INF); 1 << t];
+        let mut from = vec![Vec2d::new(self.grid.n, self.grid.m, From::None); 1 << t];
+
+        // 端点0は全ての水源をまとめた端点
+        for s in self.grid.source.iter() {
+            dp[1].set(s, self.cost(s));
+            from[1].set(s, From::Terminal);
+        }
+        for (i, h) in self.grid.house.iter().enumerate() {
+            dp[1 << (i + 1)].set(h, self.cost(h));
+            from[1 << (i + 1)].set(h, From::Terminal);
+        }
+
+        for set in 1..=full {
+            // 2つの部分木をvで合わせる
+            if set & (set - 1) != 0 {
+                for y in 0..self.grid.n as i64 {
+                    for x in 0..self.grid.m as i64 {
+                        let v = Pos { y, x };
+                        let w = self.cost(&v);
+                        let mut best = dp[set].get(&v);
+                        let mut best_from = from[set].get(&v);
+                        // 最下位ビットを含む部分集合だけを見て、重複を避ける
+                        let low = set & set.wrapping_neg();
+                        let mut sub = (set - 1) & set;
+                        while sub > 0 {
+                            if sub & low != 0 {
+                                let d = dp[sub].get(&v) + dp[set ^ sub].get(&v) - w;
+                                if d < best {
+                                    best = d;
+                                    best_from = From::Merge(sub);
+                                }
+                            }
+                            sub = (sub - 1) & set;
+                        }
+                        dp[set].set(&v, best);
+                        from[set].set(&v, best_from);
+                    }
+                }
+            }
+
+            // 木を隣のマスに伸ばす
+            let mut heap = BinaryHeap::new();
+            for y in 0..self.grid.n as i64 {
+                for x in 0..self.grid.m as i64 {
+                    let v = Pos { y, x };
+                    if dp[set].get(&v) < INF {
+                        heap.push((Reverse(dp[set].get(&v)), v));
+                    }
+                }
+            }
+            while let Some((Reverse(d), v)) = heap.pop() {
+                if dp[set].get(&v) < d {
+                    continue;
+                }
+                for np in self.neighbors(&v) {
+                    let nd = d + self.cost(&np);
+                    if nd < dp[set].get(&np) {
+                        dp[set].set(&np, nd);
+                        from[set].set(&np, From::Edge(v));
+                        heap.push((Reverse(nd), np));
+                    }
+                }
+            }
+        }
+
+        // forest[H]: 家の集合Hを、それぞれいずれかの水源に繋ぐ森の最小コスト（Hは端点0を含まない）
+        let mut forest = vec![INF; 1 << t];
+        let mut forest_from = vec![ForestFrom::None; 1 << t];
+        forest[0] = 0;
+        for set in (2..full).step_by(2) {
+            // 水源を含む1つの木
+            for (v, &d) in dp[set | 1].iter() {
+                if d < forest[set] {
+                    forest[set] = d;
+                    forest_from[set] = ForestFrom::Tree(v);
+                }
+            }
+            // 2つの森に分ける
+            let low = set & set.wrapping_neg();
+            let mut sub = (set - 1) & set;
+            while sub > 0 {
+                if sub & low != 0 && forest[sub] + forest[set ^ sub] < forest[set] {
+                    forest[set] = forest[sub] + forest[set ^ sub];
+                    forest_from[set] = ForestFrom::Split(sub);
+                }
+                sub = (sub - 1) & set;
+            }
+        }
+
+        // 森を木に分ける
+        let mut stack = vec![];
+        let mut sets = vec![full ^ 1];
+        while let Some(set) = sets.pop() {
+            match forest_from[set] {
+                ForestFrom::None => {}
+                ForestFrom::Tree(v) => stack.push((set | 1, v)),
+                ForestFrom::Split(sub) => {
+                    sets.push(sub);
+                    sets.push(set ^ sub);
+                }
+            }
+        }
+
+        // 木を復元する
+        let mut tree = vec![];
+        while let Some((set, v)) = stack.pop() {
+            match from[set].get(&v) {
+                From::None | From::Terminal => tree.push(v),
+                From::Merge(sub) => {
+                    stack.push((sub, v));
+                    stack.push((set ^ sub, v));
+                }
+                From::Edge(u) => {
+                    tree.push(v);
+                    stack.push((set, u));
+                }
+            }
+        }
+        tree.sort();
+        tree.dedup();
+        tree
+    }
+
+    /// 水源から始めて、今の木から最も近い家を順に繋いでいく
+    fn shortest_path_heuristic(&self) -> Vec<Pos> {
+        let mut in_tree = Vec2d::new(self.grid.n, self.grid.m, false);
+        let mut tree = vec![];
+        for s in self.grid.source.iter() {
+            in_tree.set(s, true);
+            tree.push(*s);
+        }
+
+        let mut remaining: Vec<Pos> = self.grid.house.clone();
+        while !remaining.is_empty() {
+            // 最初に確定した家が、今の木から最も近い家
+            let house = self.search(&tree, |p| remaining.contains(p)).unwrap();
+            remaining.retain(|h| *h != house);
+            self.add_path_to(&house, &mut in_tree, &mut tree);
+        }
+        tree
+    }
+
+    /// 水源から始めて、housesの順に今の木への最短路で繋いでいく
+    fn connect_in_order(&self, houses: &[Pos]) -> Vec<Pos> {
+        let mut in_tree = Vec2d::new(self.grid.n, self.grid.m, false);
+        let mut tree = vec![];
+        for s in self.grid.source.iter() {
+            in_tree.set(s, true);
+            tree.push(*s);
+        }
+
+        for h in houses.iter() {
+            if in_tree.get(h) {
+                continue;
+            }
+            self.search(&tree, |p| p == h);
+            self.add_path_to(h, &mut in_tree, &mut tree);
+        }
+        tree
+    }
+
+    /// 直前のsearchでの、木からpまでの最短路を木に加える
+    fn add_path_to(&self, p: &Pos, in_tree: &mut Vec2d<bool>, tree: &mut Vec<Pos>) {
+        for q in self.dijkstra.borrow().path_to(p) {
+            if !in_tree.get(&q) {
+                in_tree.set(&q, true);
+                tree.push(q);
+            }
+        }
+    }
+
+    /// 木の端点・分岐点の間のパスを外し、より安く繋ぎ直せるなら置き換える
+    ///
+    /// 1周の間に改善できなくなるか、max_passes周するか、期限を過ぎるまで繰り返す
+    pub fn improve_by_key_path_exchange(
+        &self,
+        tree: Vec<Pos>,
+        max_passes: usize,
+        deadline: &Deadline,
+    ) -> Vec<Pos> {
+        let mut in_tree = Vec2d::new(self.grid.n, self.grid.m, false);
+        for p in tree.iter() {
+            in_tree.set(p, true);
+        }
+
+        let mut is_timed_out = false;
+        for _ in 0..max_passes {
+            let mut is_improved = false;
+            self.prune_leaves(&mut in_tree);
+            // 置き換えたパスは他のパスと重ならないので、残りのパスもそのまま試せる
+            for path in self.key_paths(&in_tree) {
+                if deadline.is_passed() {
+                    is_timed_out = true;
+                    break;
+                }
+                let removed_cost: i64 = path.iter().map(|p| self.cost(p)).sum();
+                for p in path.iter() {
+                    in_tree.set(p, false);
+                }
+                match self.reconnect(&mut in_tree, removed_cost) {
+                    Some(_) => is_improved = true,
+                    None => {
+                        for p in path.iter() {
+                            in_tree.set(p, true);
+                        }
+                    }
+                }
+            }
+            if !is_improved || is_timed_out {
+                break;
+            }
+        }
+
+        self.prune_leaves(&mut in_tree);
+        let mut tree = vec![];
+        for y in 0..self.grid.n as i64 {
+            for x in 0..self.grid.m as i64 {
+                let p = Pos { y, x };
+                if in_tree.get(&p) {
+                    tree.push(p);
+                }
+            }
+        }
+        tree
+    }
+
+    fn is_terminal(&self, p: &Pos) -> bool {
+        self.grid.house.contains(p) || self.grid.source.contains(p)
+    }
+
+    fn degree(&self, in_tree: &Vec2d<bool>, p: &Pos) -> usize {
+        self.neighbors(p).filter(|np| in_tree.get(np)).count()
+    }
+
+    /// 端点ではない葉を取り除く
+    fn prune_leaves(&self, in_tree: &mut Vec2d<bool>) {
+        let mut queue = VecDeque::new();
+        for y in 0..self.grid.n as i64 {
+            for x in 0..self.grid.m as i64 {
+                queue.push_back(Pos { y, x });
+            }
+        }
+        while let Some(p) = queue.pop_front() {
+            if !in_tree.get(&p) || self.is_terminal(&p) || self.degree(in_tree, &p) > 1 {
+                continue;
+            }
+            in_tree.set(&p, false);
+            for np in self.neighbors(&p) {
+                if in_tree.get(&np) {
+                    queue.push_back(np);
+                }
+            }
+        }
+    }
+
+    /// 端点と次数3以上のマスをキーとして、キーの間を結ぶパスの内側のマスを列挙する
+    fn key_paths(&self, in_tree: &Vec2d<bool>) -> Vec<Vec<Pos>> {
+        let is_key = |p: &Pos| self.is_terminal(p) || self.degree(in_tree, p) >= 3;
+        let mut seen = Vec2d::new(self.grid.n, self.grid.m, false);
+        let mut paths = vec![];
+        for y in 0..self.grid.n as i64 {
+            for x in 0..self.grid.m as i64 {
+                let start = Pos { y, x };
+                if !in_tree.get(&start) || seen.get(&start) || is_key(&start) {
+                    continue;
+                }
+                // startを含む、キーではないマスの連なりを集める
+                let mut path = vec![];
+                let mut stack = vec![start];
+                seen.set(&start, true);
+                while let Some(p) = stack.pop() {
+                    path.push(p);
+                    for np in self.neighbors(&p) {
+                        if in_tree.get(&np) && !seen.get(&np) && !is_key(&np) {
+                            seen.set(&np, true);
+                            stack.push(np);
+                        }
+                    }
+                }
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// 水源に繋がっていない家を含む成分を、水源に繋がっている成分へupperより安い最短路で繋ぎ、そのコストを返す
+    /// 全ての家が繋がっていればコスト0を返す
+    fn reconnect(&self, in_tree: &mut Vec2d<bool>, upper: i64) -> Option<i64> {
+        let mut watered = Vec2d::new(self.grid.n, self.grid.m, false);
+        let mut queue: VecDeque<Pos> = self
+            .grid
+            .source
+            .iter()
+            .filter(|s| in_tree.get(s))
+            .cloned()
+            .collect();
+        for s in queue.iter() {
+            watered.set(s, true);
+        }
+        let mut sources = vec![];
+        while let Some(p) = queue.pop_front() {
+            sources.push(p);
+            for np in self.neighbors(&p) {
+                if in_tree.get(&np) && !watered.get(&np) {
+                    watered.set(&np, true);
+                    queue.push_back(np);
+                }
+            }
+        }
+
+        let Some(house) = self.grid.house.iter().find(|h| !watered.get(h)) else {
+            return (0 < upper).then_some(0);
+        };
+        // 繋がっていない成分は、家から木を辿れるマス全て
+        self.search(&sources, |_| false);
+        let dijkstra = self.dijkstra.borrow();
+        let mut component = vec![*house];
+        let mut seen = Vec2d::new(self.grid.n, self.grid.m, false);
+        seen.set(house, true);
+        let mut i = 0;
+        while i < component.len() {
+            let p = component[i];
+            i += 1;
+            for np in self.neighbors(&p) {
+                if in_tree.get(&np) && !seen.get(&np) {
+                    seen.set(&np, true);
+                    component.push(np);
+                }
+            }
+        }
+        // 成分の他の家も繋がっていないといけないが、成分を丸ごと繋ぐので問題ない
+        let attach = *component
+            .iter()
+            .min_by_key(|p| dijkstra.dist(p) - self.cost(p))?;
+        if dijkstra.dist(&attach) >= INF {
+            return None;
+        }
+        // 水に繋がっているマスとattachの間のマス
+        let path = dijkstra.path_to(&attach);
+        let path = path[1..path.len() - 1].to_vec();
+        let cost = path.iter().map(|p| self.cost(p)).sum();
+        if cost >= upper {
+            return None;
+        }
+
+        // 他にも繋がっていない成分があれば、繋ぎ直しは失敗とする
+        let added: Vec<Pos> = path.into_iter().filter(|p| !in_tree.get(p)).collect();
+        for p in added.iter() {
+            in_tree.set(p, true);
+        }
+        if self.reconnect_check(in_tree) {
+            Some(cost)
+        } else {
+            for p in added.iter() {
+                in_tree.set(p, false);
+            }
+            None
+        }
+    }
+
+    fn reconnect_check(&self, in_tree: &Vec2d<bool>) -> bool {
+        let mut watered = Vec2d::new(self.grid.n, self.grid.m, false);
+        let mut queue: VecDeque<Pos> = self
+            .grid
+            .source
+            .iter()
+            .filter(|s| in_tree.get(s))
+            .cloned()
+            .collect();
+        for s in queue.iter() {
+            watered.set(s, true);
+        }
+        while let Some(p) = queue.pop_front() {
+            for np in self.neighbors(&p) {
+                if in_tree.get(&np) && !watered.get(&np) {
+                    watered.set(&np, true);
+                    queue.push_back(np);
+                }
+            }
+        }
+        self.grid.house.iter().all(|h| watered.get(h))
+    }
+
+    /// startsから、入ったマスの重みの和を距離として探索し、is_targetを満たすマスが最初に確定したらそのマスを返す
+    /// 距離と最短路は、次に探索するまでself.dijkstraから読める
+    fn search(&self, starts: &[Pos], is_target: impl FnMut(&Pos) -> bool) -> Option<Pos> {
+        self.dijkstra
+            .borrow_mut()
+            .run(starts, INF, |p| self.cost(p), is_target, |_| 0)
+    }
+
+    fn neighbors(&self, p: &Pos) -> impl Iterator<Item = Pos> {
+        self.grid.is_used.neighbors(p)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    /// 重みが一様乱数のn行m列の盤面に、w個の水源とk個の家を置く
+    pub(crate) fn random_grid(seed: u64, n: usize, m: usize, w: usize, k: usize) -> Grid {
+        let mut s = seed * 2 + 1;
+        let mut next = move |high: usize| {
+            s ^= s << 13;
+            s ^= s >> 7;
+            s ^= s << 17;
+            (s % high as u64) as usize
+        };
+        let mut weight = Vec2d::new(n, m, 0);
+        for y in 0..n as i64 {
+            for x in 0..m as i64 {
+                weight.set(&Pos { y, x }, 1 + next(100) as i64);
+            }
+        }
+        let mut terminals: Vec<Pos> = vec![];
+        while terminals.len() < w + k {
+            let p = Pos {
+                y: next(n) as i64,
+                x: next(m) as i64,
+            };
+            if !terminals.contains(&p) {
+                terminals.push(p);
+            }
+        }
+        let house = terminals.split_off(w);
+        let mut is_used = Vec2d::new(n, m, false);
+        for p in terminals.iter().chain(house.iter()) {
+            is_used.set(p, true);
+        }
+        Grid::new(weight, is_used, house, terminals)
+    }
+
+    pub(crate) fn connects_all_houses(solver: &SteinerSolver, tree: &[Pos]) -> bool {
+        let mut in_tree = Vec2d::new(solver.grid.n, solver.grid.m, false);
+        for p in tree.iter() {
+            in_tree.set(p, true);
+        }
+        solver.reconnect_check(&in_tree)
+    }
+
+    #[test]
+    fn dreyfus_wagner_is_not_worse_than_heuristic() {
+        for w in 1..=3 {
+            for seed in 0..10 {
+                let grid = random_grid(seed, 25, 30, w, 4);
+                let solver = SteinerSolver::new(&grid);
+                let exact = solver.dreyfus_wagner();
+                assert!(connects_all_houses(&solver, &exact));
+                for order in [HouseOrder::Prim, HouseOrder::NearestFirst] {
+                    let heuristic = solver.build(order);
+                    assert!(
+                        solver.tree_cost(&exact) <= solver.tree_cost(&heuristic),
+                        "w = {}, seed = {}, {:?}: {} > {}",
+                        w,
+                        seed,
+                        order,
+                        solver.tree_cost(&exact),
+                        solver.tree_cost(&heuristic)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn key_path_exchange_keeps_houses_connected() {
+        for w in 1..=3 {
+            for seed in 0..40 {
+                let grid = random_grid(seed, 25, 30, w, 8);
+                let solver = SteinerSolver::new(&grid);
+                for order in [HouseOrder::Prim, HouseOrder::FarthestFirst] {
+                    let tree = solver.build(order);
+                    let clock = Clock::wall();
+                    let deadline = Deadline::new(&clock, f64::INFINITY);
+                    let improved = solver.improve_by_key_path_exchange(tree.clone(), 5, &deadline);
+                    assert!(
+                        connects_all_houses(&solver, &improved),
+                        "w = {}, seed = {}, {:?}",
+                        w,
+                        seed,
+                        order
+                    );
+                    assert!(solver.tree_cost(&improved) <= solver.tree_cost(&tree));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{def::*, estimator::*};
+
+/// 1つのマスを割る時の叩き方
+#[derive(Debug, Clone)]
+pub struct StrikePlan {
+    /// 各回で累積何パワーまで叩くか（最後はS_MAX）
+    pub targets: Vec<i64>,
+    /// 割るまでの、叩く回数 * C + パワーの和の期待値
+    pub expected_cost: f64,
+}
+
+/// 頑丈度の予測分布とCから、割るまでのコストの期待値が最小になる叩き方を求める
+///
+/// 累積のパワーは、S_MINからS_MAXまでをlevel_ratio倍ずつ区切った値から選ぶ
+pub struct StrikePlanner {
+    c: i64,
+    levels: Vec<i64>,
+}
+
+impl StrikePlanner {
+    pub fn new(c: i64, level_ratio: f64) -> StrikePlanner {
+        let mut levels = vec![];
+        let mut level = S_MIN as f64;
+        while (level as i64) < S_MAX {
+            if levels.last() != Some(&(level as i64)) {
+                levels.push(level as i64);
+            }
+            level *= level_ratio;
+        }
+        levels.push(S_MAX);
+        StrikePlanner { c, levels }
+    }
+
+    /// 既にdamageだけ与えて割れていないマスの叩き方
+    ///
+    /// g[i]まで叩いて割れていない時の残りのコストの期待値にs[i] = P(h > g[i])を掛けたものをf[i]とすると、
+    /// f[i] = min_{j > i} s[i] * (C + g[j] - g[i]) + f[j]
+    pub fn plan(&self, estimate: &Estimate, damage: i64) -> StrikePlan {
+        let std = f64::max(estimate.std, 1e-3);
+        // 頑丈度はdamageより大きいので、予測値がそれより小さければdamageに合わせる
+        let mean = if damage > 0 {
+            f64::max(estimate.mean, (damage as f64).log2())
+        } else {
+            estimate.mean
+        };
+        let mut g = vec![damage];
+        g.extend(self.levels.iter().filter(|&&l| l > damage));
+        let s: Vec<f64> = g
+            .iter()
+            .map(|&x| {
+                if x == 0 {
+                    1.
+                } else if x >= S_MAX {
+                    0.
+                } else {
+                    1. - normal_cdf(((x as f64).log2() - mean) / std)
+                }
+            })
+            .collect();
+
+        let m = g.len();
+        let mut f = vec![0.; m];
+        let mut next = vec![m - 1; m];
+        for i in (0..m - 1).rev() {
+            f[i] = f64::MAX;
+            for j in i + 1..m {
+                let cost = s[i] * (self.c + g[j] - g[i]) as f64 + f[j];
+                if cost < f[i] {
+                    f[i] = cost;
+                    next[i] = j;
+                }
+            }
+        }
+
+        let mut targets = vec![];
+        let mut i = 0;
+        while i < m - 1 {
+            i = next[i];
+            targets.push(g[i]);
+        }
+        StrikePlan {
+            targets,
+            // 頑丈度がdamageより大きいという条件のもとでの期待値
+            expected_cost: if m == 1 {
+                0.
+            } else {
+                f[0] / f64::max(s[0], 1e-12)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_targets_increase_to_s_max() {
+        for c in [1, 8, 128] {
+            let planner = StrikePlanner::new(c, 1.08);
+            for mean in [3., 6., 9., 12.5] {
+                for std in [0., 0.3, 1.5] {
+                    for damage in [0, 50, 1000, 4999] {
+                        let plan = planner.plan(&Estimate { mean, std }, damage);
+                        assert!(!plan.targets.is_empty());
+                        assert!(plan.targets[0] > damage);
+                        assert!(plan.targets.windows(2).all(|t| t[0] < t[1]));
+                        assert_eq!(*plan.targets.last().unwrap(), S_MAX);
+                        assert!(plan.expected_cost >= c as f64);
+                    }
+                }
+            }
+        }
+    }
+}
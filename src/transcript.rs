@@ -0,0 +1,117 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::{def::*, interactor::Response, util::time};
+
+/// 掘削を行ったsolverの処理段階
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    /// 最初に格子状に頑丈度を調べる
+    Survey,
+    /// 選択経路の周りを調べる
+    Investigate,
+    /// 選択経路を割る
+    Destroy,
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Survey => "survey",
+            Phase::Investigate => "investigate",
+            Phase::Destroy => "destroy",
+        }
+    }
+}
+
+/// 掘削とジャッジの応答の記録
+///
+/// 入力（N W K C M、水源、家）に続けて、`y x P r elapsed phase`を1行ずつ書き出す
+/// Mは盤面の列数で、公式の入力にはない
+/// 各掘削の前には、その前にsolverが時計から読んだ時刻を`clock t`として書き出す
+/// `ReplayInteractor::from_transcript`でそのまま再生できる
+pub struct Transcript {
+    writer: BufWriter<File>,
+}
+
+impl Transcript {
+    pub fn create(path: &str, input: &Input) -> Transcript {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        writeln!(
+            writer,
+            "{} {} {} {} {}",
+            input.n, input.w, input.k, input.c, input.m
+        )
+        .unwrap();
+        for p in input.source.iter().chain(input.house.iter()) {
+            writeln!(writer, "{} {}", p.y, p.x).unwrap();
+        }
+        Transcript { writer }
+    }
+
+    pub fn record(&mut self, pos: &Pos, power: i64, response: Response, phase: Phase) {
+        writeln!(
+            self.writer,
+            "{} {} {} {} {:.4} {}",
+            pos.y,
+            pos.x,
+            power,
+            response.code(),
+            time::elapsed_seconds(),
+            phase.name()
+        )
+        .unwrap();
+    }
+
+    /// solverが時計から読んだ時刻
+    pub fn record_clock(&mut self, readings: &[f64]) {
+        for t in readings.iter() {
+            writeln!(self.writer, "clock {}", t).unwrap();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        interactor::{InMemoryInteractor, ReplayInteractor, Response},
+        judge::Judge,
+        params::Params,
+        solver::Solver,
+    };
+
+    #[test]
+    fn replay_of_transcript_reaches_all_connected() {
+        // 乱数は並列に走る他のテストと共有しているので、乱数を使う焼きなましとランダムな順番の初期解は使わない
+        let params = Params {
+            refine_time_limit: 0.,
+            optimize_time_limit: 0.,
+            reroute_time_limit: 0.,
+            route_random_restarts: 0,
+            ..Params::default()
+        };
+        let path =
+            std::env::temp_dir().join(format!("ahc018_transcript_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let judge = Judge::generate_with_size(3, 20, 20);
+        let interactor = Box::new(InMemoryInteractor::from_judge(judge));
+        let mut solver = Solver::new(interactor, params.clone());
+        solver.record_transcript(path);
+        assert_eq!(solver.solve(), Response::AllConnected);
+
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(text.lines().any(|line| line.starts_with("clock ")));
+        let interactor = Box::new(ReplayInteractor::from_transcript(&text));
+        let mut solver = Solver::new(interactor, params);
+        // 記録と異なる掘削をすると不正な掘削として扱われるので、最後まで記録通りに掘削している
+        assert_eq!(solver.solve(), Response::AllConnected);
+    }
+}
@@ -1,5 +1,4 @@
 #[allow(unused_features)]
-
 pub mod rnd {
     #[allow(unused)]
     static mut S: usize = 88172645463325252;
@@ -27,7 +26,7 @@ pub mod rnd {
     }
 
     #[allow(unused)]
-    pub fn shuffle<I>(vec: &mut Vec<I>) {
+    pub fn shuffle<I>(vec: &mut [I]) {
         for i in 0..vec.len() {
             let j = gen_range(0, vec.len());
             vec.swap(i, j);
@@ -59,7 +58,7 @@ pub mod time {
 }
 
 #[allow(unused)]
-pub fn min_index<I>(vec: &Vec<I>) -> usize
+pub fn min_index<I>(vec: &[I]) -> usize
 where
     I: Ord,
 {
@@ -69,5 +68,62 @@ where
             ret = i;
         }
     }
-    return ret;
+    ret
+}
+
+#[derive(Debug, Clone)]
+pub struct UnionFind {
+    par: Vec<usize>,
+    size: Vec<usize>,
+}
+
+#[allow(unused)]
+impl UnionFind {
+    pub fn new(n: usize) -> UnionFind {
+        UnionFind {
+            par: (0..n).collect(),
+            size: vec![1; n],
+        }
+    }
+
+    /// vを1頂点だけの集合に戻す
+    /// vと同じ集合の頂点も全て戻さないと、集合が壊れる
+    pub fn reset(&mut self, v: usize) {
+        self.par[v] = v;
+        self.size[v] = 1;
+    }
+
+    pub fn find(&mut self, v: usize) -> usize {
+        let mut root = v;
+        while self.par[root] != root {
+            root = self.par[root];
+        }
+        // 経路圧縮
+        let mut cur = v;
+        while self.par[cur] != root {
+            let next = self.par[cur];
+            self.par[cur] = root;
+            cur = next;
+        }
+        root
+    }
+
+    pub fn unite(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        let (a, b) = if self.size[a] < self.size[b] {
+            (b, a)
+        } else {
+            (a, b)
+        };
+        self.par[b] = a;
+        self.size[a] += self.size[b];
+        true
+    }
+
+    pub fn same(&mut self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
+    }
 }